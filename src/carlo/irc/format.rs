@@ -0,0 +1,97 @@
+//! mIRC formatting codes.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+const BOLD: char = '\x02';
+const COLOR: char = '\x03';
+const HEX_COLOR: char = '\x04';
+/// Codes that toggle a style and take no argument: bold, italics, strikethrough, monospace,
+/// underline, reverse and reset
+const TOGGLES: [char; 7] = [BOLD, '\x1d', '\x1e', '\x11', '\x1f', '\x16', '\x0f'];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Color {
+    Green,
+    Red,
+    Yellow,
+    Grey,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Color::Green => "03",
+            Color::Red => "04",
+            Color::Yellow => "08",
+            Color::Grey => "14",
+        }
+    }
+}
+
+pub fn bold(text: &str) -> String {
+    format!("{}{}{}", BOLD, text, BOLD)
+}
+
+pub fn colored(text: &str, color: Color) -> String {
+    // always use two digits, so that text starting with a digit is not mistaken for a color
+    format!("{}{}{}{}", COLOR, color.code(), text, COLOR)
+}
+
+/// Remove all mIRC formatting codes from `text`, including color arguments.
+///
+/// `irc::proto::FormattedStringExt` is not used because it leaves the second digit of color
+/// codes such as `\x0304` in the text.
+pub fn strip_formatting(text: &str) -> String {
+    fn skip_while(chars: &mut Peekable<Chars>, max: usize, pred: fn(&char) -> bool) -> usize {
+        let mut n = 0;
+        while n < max && chars.peek().is_some_and(pred) {
+            chars.next();
+            n += 1;
+        }
+        n
+    }
+    fn skip_color(chars: &mut Peekable<Chars>, len: usize, pred: fn(&char) -> bool) {
+        if skip_while(chars, len, pred) > 0 && chars.peek() == Some(&',') {
+            let mut lookahead = chars.clone();
+            lookahead.next();
+            if lookahead.peek().is_some_and(pred) {
+                chars.next();
+                skip_while(chars, len, pred);
+            }
+        }
+    }
+
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            COLOR => skip_color(&mut chars, 2, char::is_ascii_digit),
+            HEX_COLOR => skip_color(&mut chars, 6, char::is_ascii_hexdigit),
+            c if TOGGLES.contains(&c) => (),
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_our_formatting() {
+        let text = format!("{} is {}", bold("job"), colored("42 FAILURE", Color::Red));
+        assert_eq!(strip_formatting(&text), "job is 42 FAILURE");
+    }
+
+    #[test]
+    fn strip_color_arguments() {
+        assert_eq!(strip_formatting("\x034,12a\x03,b\x0399,c"), "a,b,c");
+        assert_eq!(
+            strip_formatting("\x04ff0000red\x0f \x1ditalic\x1d"),
+            "red italic"
+        );
+        assert_eq!(strip_formatting("no formatting, 1,2"), "no formatting, 1,2");
+    }
+}
@@ -1,3 +1,5 @@
+pub mod format;
+
 use irc::client::prelude::{Client, IrcClient};
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
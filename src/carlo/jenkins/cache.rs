@@ -1,8 +1,12 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
 
-#[derive(Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
-pub struct Name(String);
+use super::{BuildNumber, BuildResult, BuildUrl};
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Name(pub String);
 
 impl fmt::Display for Name {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -10,8 +14,8 @@ impl fmt::Display for Name {
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
-pub struct Timestamp(u64);
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Timestamp(pub u64);
 
 impl fmt::Display for Timestamp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -19,11 +23,52 @@ impl fmt::Display for Timestamp {
     }
 }
 
-type ServerID = String;
+pub type ServerID = String;
+
+/// What we remember about the last completed build of a job.
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct Entry {
+    pub last_completed: BuildNumber,
+    pub timestamp: Timestamp,
+    /// Result of `last_completed`
+    #[serde(default)]
+    pub result: Option<BuildResult>,
+    /// Older builds that were still running when `last_completed` finished
+    #[serde(default)]
+    pub pending: Vec<BuildNumber>,
+    /// Running builds whose start has been announced
+    #[serde(default)]
+    pub started: Vec<BuildNumber>,
+    /// URL of `last_completed`
+    #[serde(default)]
+    pub url: Option<BuildUrl>,
+    /// Start of the first build of the current series of unsuccessful builds, if
+    /// `last_completed` was not successful
+    #[serde(default)]
+    pub broken_since: Option<Timestamp>,
+    /// Result of the last completed build that was not interrupted
+    #[serde(default)]
+    pub decisive_result: Option<BuildResult>,
+}
 
-#[derive(Debug, Clone)]
+impl Entry {
+    /// The result that tells whether the job is failing: the one of its last completed build
+    /// that was neither aborted nor not built.
+    pub fn outcome(&self) -> Option<&BuildResult> {
+        self.decisive_result
+            .as_ref()
+            .or(self.result.as_ref())
+            .filter(|result| !result.is_interrupted())
+    }
+}
+
+/// The last completed build of each job, by server id (`JenkinsConfig.id`) and job name.
+/// State files written by older versions used the server URL instead of its id; their
+/// entries are moved under the id by `migrate` when they are loaded.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(transparent)]
 pub struct Cache {
-    cache: HashMap<ServerID, HashMap<Name, Timestamp>>,
+    cache: HashMap<ServerID, HashMap<Name, Entry>>,
 }
 
 impl Cache {
@@ -33,24 +78,76 @@ impl Cache {
         }
     }
 
-    pub fn insert(
-        &mut self,
-        server: &ServerID,
-        name: &Name,
-        timestamp: &Timestamp,
-    ) -> Option<Timestamp> {
+    /// Read a cache previously written by `save`. Returns `Ok(None)` if the file does not
+    /// exist.
+    pub fn load(path: &str) -> Result<Option<Cache>, String> {
+        match File::open(path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .map(Some)
+                .map_err(|err| err.to_string()),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    /// Write the cache to `path`. The contents are written to a temporary file first, which
+    /// is then renamed, so that `path` always contains a complete cache.
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let tmp_path = format!("{}.tmp", path);
+        File::create(&tmp_path)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                serde_json::to_writer(&mut writer, self)?;
+                writer.flush()?;
+                writer.get_ref().sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .map_err(|err| err.to_string())
+    }
+
+    pub fn get(&self, server: &ServerID, name: &Name) -> Option<&Entry> {
+        self.cache
+            .get(server)
+            .and_then(|sub_cache| sub_cache.get(name))
+    }
+
+    pub fn insert(&mut self, server: &ServerID, name: &Name, entry: &Entry) -> Option<Entry> {
         self.cache
             .entry(server.clone())
-            .or_insert(HashMap::new())
-            .insert(name.clone(), *timestamp)
+            .or_default()
+            .insert(name.clone(), entry.clone())
     }
 
-    pub fn prune_except(&mut self, server: &ServerID, build_names: &Vec<&Name>) {
-        fn in_build_names(name: &Name, build_names: &Vec<&Name>) -> bool {
-            build_names.iter().any(|build_name| name == *build_name)
-        }
-        let sub_cache = self.cache.entry(server.clone()).or_insert(HashMap::new());
-        sub_cache.retain(|ref name, ref mut _val| in_build_names(*name, build_names));
+    /// All the entries, by server and job name.
+    pub fn entries(&self) -> impl Iterator<Item = (&ServerID, &Name, &Entry)> {
+        self.cache.iter().flat_map(|(server, sub_cache)| {
+            sub_cache
+                .iter()
+                .map(move |(name, entry)| (server, name, entry))
+        })
+    }
+
+    /// Move the entries saved under the URL of each server in `servers` to its id, and drop
+    /// the entries of the servers that are not configured anymore.
+    pub fn migrate(&mut self, servers: &[(&str, &ServerID)]) {
+        servers.iter().for_each(|(url, id)| {
+            if let Some(sub_cache) = self.cache.remove(*url) {
+                info!("Moving the saved builds of {} to {}", url, id);
+                self.cache.entry((*id).clone()).or_insert(sub_cache);
+            }
+        });
+        self.cache.retain(|server, _| {
+            let configured = servers.iter().any(|(_, id)| *id == server);
+            if !configured {
+                info!("Dropping the saved builds of unknown server {}", server);
+            }
+            configured
+        });
+    }
+
+    pub fn prune_except(&mut self, server: &ServerID, build_names: &[&Name]) {
+        let sub_cache = self.cache.entry(server.clone()).or_default();
+        sub_cache.retain(|name, _val| build_names.contains(&name));
         info!(
             "Builds kept after prune_builds_except(): {}",
             sub_cache.len()
@@ -60,6 +157,7 @@ impl Cache {
 
 #[cfg(test)]
 pub mod tests {
+    use super::super::tests::{build_numbers, build_results};
     use super::*;
     use proptest::prelude::*;
 
@@ -81,16 +179,59 @@ pub mod tests {
         }
     }
 
+    prop_compose! {
+        [pub] fn entries()(last_completed in build_numbers(),
+                           timestamp in timestamps(),
+                           result in prop::option::of(build_results()),
+                           pending in prop::collection::vec(build_numbers(), 0..3),
+                           started in prop::collection::vec(build_numbers(), 0..3)) -> Entry {
+            Entry { last_completed, timestamp, result, pending, started, ..Entry::default() }
+        }
+    }
+
     prop_compose! {
         [pub] fn caches(min_servers: usize, max_servers: usize, min_names: usize, max_names: usize)
             (cache in prop::collection::hash_map(
                 server_ids(),
-                prop::collection::hash_map(names(), timestamps(), min_servers..max_servers),
+                prop::collection::hash_map(names(), entries(), min_servers..max_servers),
                 min_names..max_names)) -> Cache {
             Cache { cache }
         }
     }
 
+    proptest! {
+        #[test]
+        fn save_and_load(cache in caches(1, 5, 1, 10)) {
+            let file_name = format!("carlo-cache-{}.json", std::process::id());
+            let path = std::env::temp_dir().join(file_name);
+            let path = path.to_str().unwrap();
+            cache.save(path).unwrap();
+            let loaded = Cache::load(path).unwrap().unwrap();
+            fs::remove_file(path).unwrap();
+            assert_eq!(loaded.cache, cache.cache);
+        }
+    }
+
+    #[test]
+    fn migrate_url_keys() {
+        let entry = Entry {
+            last_completed: BuildNumber(3),
+            timestamp: Timestamp(3000),
+            ..Entry::default()
+        };
+        let name = Name(String::from("nightly"));
+        let mut cache = Cache::new();
+        let (url, id) = (String::from("http://ci/api/json"), String::from("ci"));
+        let other = String::from("other");
+        cache.insert(&url, &name, &entry);
+        cache.insert(&String::from("http://gone"), &name, &entry);
+        cache.insert(&other, &name, &entry);
+        cache.migrate(&[(url.as_str(), &id), ("http://other", &other)]);
+        assert_eq!(cache.get(&id, &name), Some(&entry));
+        assert_eq!(cache.get(&other, &name), Some(&entry));
+        assert_eq!(cache.entries().count(), 2);
+    }
+
     /// Count the number of names in each subcache and return them as a new hashmap
     fn count_sizes(cache: &Cache) -> HashMap<ServerID, usize> {
         let mut counts = HashMap::new();
@@ -109,7 +250,7 @@ pub mod tests {
                 let server = cache.cache.keys().next().unwrap();
                 a_server = server.clone();
             }
-            cache.prune_except(&a_server, &Vec::new());
+            cache.prune_except(&a_server, &[]);
             cache.cache.iter().for_each(|(server, subcache)| {
                 if *server == a_server {
                     assert_eq!(subcache.len(), 0);
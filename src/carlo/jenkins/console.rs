@@ -0,0 +1,204 @@
+//! Excerpts of the console output of failed builds.
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use reqwest::blocking::{Client, Response};
+
+use super::event::BuildEvent;
+use crate::config::{ConsoleConfig, JenkinsConfig};
+
+/// Send a request for the plain text console output of `build` from byte `start` on.
+fn progressive_text(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    build: &BuildEvent,
+    head: bool,
+    start: u64,
+) -> Result<Response, String> {
+    let url = format!(
+        "{}/logText/progressiveText",
+        build.url.0.trim_end_matches('/')
+    );
+    let request = if head {
+        client.head(url)
+    } else {
+        client.get(url)
+    };
+    request
+        .query(&[("start", start)])
+        .basic_auth(&j_config.user, Some(&j_config.token))
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| err.to_string())
+}
+
+/// Download at most the last `max_bytes` bytes of the console output of `build`. The size
+/// of the output is read from the `X-Text-Size` header of a HEAD request first, so that only
+/// the end of the output is downloaded. If Jenkins does not tell the size, the whole output
+/// is read, and thrown away as it arrives.
+fn tail(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    build: &BuildEvent,
+    max_bytes: u64,
+) -> Result<String, String> {
+    let size = progressive_text(client, j_config, build, true, 0)?
+        .headers()
+        .get("X-Text-Size")
+        .and_then(|size| size.to_str().ok())
+        .and_then(|size| size.parse::<u64>().ok());
+    let start = match size {
+        Some(size) => size.saturating_sub(max_bytes),
+        None => {
+            warn!(
+                "Jenkins did not tell the size of the console output of build #{} of job {}",
+                build.number, build.name
+            );
+            0
+        }
+    };
+    let mut response = progressive_text(client, j_config, build, false, start)?;
+    let max_bytes = max_bytes as usize;
+    let mut output = Vec::new();
+    let mut buffer = [0; 8192];
+    let mut truncated = start > 0;
+    loop {
+        let read = response.read(&mut buffer).map_err(|err| err.to_string())?;
+        if read == 0 {
+            break;
+        }
+        output.extend_from_slice(&buffer[..read]);
+        if output.len() > 2 * max_bytes {
+            output.drain(..output.len() - max_bytes);
+            truncated = true;
+        }
+    }
+    if output.len() > max_bytes {
+        output.drain(..output.len() - max_bytes);
+        truncated = true;
+    }
+    let output = String::from_utf8_lossy(&output).into_owned();
+    if truncated {
+        // The first line is probably incomplete
+        Ok(output
+            .split_once('\n')
+            .map(|(_, rest)| rest.to_string())
+            .unwrap_or_default())
+    } else {
+        Ok(output)
+    }
+}
+
+/// Extract the lines worth posting from `output`: the last lines matching one of the
+/// patterns, or simply the last lines if there are no patterns. ANSI escape sequences and
+/// blank lines are dropped.
+fn excerpt(output: &str, console: &ConsoleConfig) -> Vec<String> {
+    let lines: Vec<String> = output
+        .lines()
+        .map(|line| console.ansi.replace_all(line, "").trim_end().to_string())
+        .filter(|line| !line.trim().is_empty())
+        .filter(|line| {
+            console.patterns.is_empty()
+                || console.patterns.iter().any(|pattern| pattern.matches(line))
+        })
+        .collect();
+    let skip = lines.len().saturating_sub(console.lines);
+    lines.into_iter().skip(skip).collect()
+}
+
+/// Write `lines` to a file of `dir` named after `build`, and return its URL under `url`.
+fn publish(dir: &str, url: &str, build: &BuildEvent, lines: &[String]) -> Result<String, String> {
+    let name = format!("{}-{}-{}.txt", build.server, build.name.0, build.number).replace(
+        |c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '.',
+        "_",
+    );
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    fs::write(Path::new(dir).join(&name), contents)
+        .map_err(|err| format!("could not write console excerpt to {}: {}", dir, err))?;
+    Ok(format!("{}/{}", url.trim_end_matches('/'), name))
+}
+
+/// Return the lines to post about the console output of `build`: the excerpt itself, or a
+/// link to the file it was written to if `file_dir` is set.
+pub fn fetch(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    console: &ConsoleConfig,
+    build: &BuildEvent,
+) -> Vec<String> {
+    let lines = match tail(client, j_config, build, console.max_bytes) {
+        Ok(output) => excerpt(&output, console),
+        Err(err) => {
+            error!(
+                "Could not fetch console output of build #{} of job {}: {}",
+                build.number, build.name, err
+            );
+            return Vec::new();
+        }
+    };
+    if lines.is_empty() {
+        return lines;
+    }
+    match (&console.file_dir, &console.file_url) {
+        (Some(dir), Some(url)) => match publish(dir, url, build, &lines) {
+            Ok(url) => vec![format!("Console output: {}", url)],
+            Err(err) => {
+                error!("{}", err);
+                Vec::new()
+            }
+        },
+        _ => lines,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn a_console(lines: usize, patterns: &[&str]) -> ConsoleConfig {
+        let patterns: Vec<String> = patterns.iter().map(|p| format!("{:?}", p)).collect();
+        let toml = format!("lines = {}\npatterns = [{}]", lines, patterns.join(", "));
+        toml::from_str(&toml).unwrap()
+    }
+
+    const OUTPUT: &str = "Started by user alice\n\
+                          \x1b[1mcompiling\x1b[0m foo\n\
+                          error: cannot find value `x`\n\
+                          \n\
+                          warning: unused variable\r\n\
+                          error: aborting due to previous error\n\
+                          Finished: FAILURE\n";
+
+    #[test]
+    fn excerpt_last_lines() {
+        assert_eq!(
+            excerpt(OUTPUT, &a_console(3, &[])),
+            vec![
+                "warning: unused variable",
+                "error: aborting due to previous error",
+                "Finished: FAILURE"
+            ]
+        );
+        assert_eq!(excerpt(OUTPUT, &a_console(10, &[])).len(), 6);
+        assert_eq!(excerpt(OUTPUT, &a_console(10, &[]))[1], "compiling foo");
+    }
+
+    #[test]
+    fn excerpt_matching_lines() {
+        assert_eq!(
+            excerpt(OUTPUT, &a_console(5, &["/^error/", "Finished: *"])),
+            vec![
+                "error: cannot find value `x`",
+                "error: aborting due to previous error",
+                "Finished: FAILURE"
+            ]
+        );
+        assert_eq!(
+            excerpt(OUTPUT, &a_console(1, &["/^error/"])),
+            vec!["error: aborting due to previous error"]
+        );
+    }
+}
@@ -0,0 +1,554 @@
+//! Additional information about single builds, fetched from the Jenkins build API.
+
+use reqwest::blocking::Client;
+use reqwest::{Error, StatusCode};
+
+use super::event::{BuildEvent, Change, Stage, StartEvent, TestCounts};
+use super::{BuildDuration, BuildResult, BuildUrl};
+use crate::carlo::Template;
+use crate::config::{Config, JenkinsConfig};
+
+const USER_TREE: &str = "id,fullName";
+
+/// Fields of the causes and parameters of a build
+const ACTION_TREE: &str = "causes[shortDescription],parameters[name,value]";
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JUser {
+    id: Option<String>,
+    full_name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JChange {
+    author: Option<JUser>,
+    author_email: Option<String>,
+    #[serde(default)]
+    msg: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct JChangeSet {
+    #[serde(default)]
+    items: Vec<JChange>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JCause {
+    short_description: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct JParameter {
+    name: String,
+    /// Missing for sensitive parameters such as passwords
+    #[serde(default)]
+    value: serde_json::Value,
+}
+
+/// An action of a build; only causes, parameters and test results are of interest here.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JAction {
+    #[serde(default)]
+    causes: Vec<JCause>,
+    #[serde(default)]
+    parameters: Vec<JParameter>,
+    fail_count: Option<u32>,
+    skip_count: Option<u32>,
+    total_count: Option<u32>,
+}
+
+impl JAction {
+    fn test_counts(&self) -> Option<TestCounts> {
+        Some(TestCounts {
+            failed: self.fail_count?,
+            skipped: self.skip_count.unwrap_or(0),
+            total: self.total_count?,
+        })
+    }
+}
+
+/// Why the build was started, e.g. `Started by timer`, from the actions of a build.
+fn causes(actions: &[Option<JAction>]) -> Vec<String> {
+    let mut causes: Vec<String> = Vec::new();
+    actions
+        .iter()
+        .flatten()
+        .flat_map(|action| action.causes.iter())
+        .for_each(|cause| {
+            if !causes.contains(&cause.short_description) {
+                causes.push(cause.short_description.clone());
+            }
+        });
+    causes
+}
+
+/// The parameters of a build and their values, from the actions of a build.
+fn parameters(actions: &[Option<JAction>]) -> Vec<(String, String)> {
+    actions
+        .iter()
+        .flatten()
+        .flat_map(|action| action.parameters.iter())
+        .map(|parameter| {
+            let value = match parameter.value {
+                serde_json::Value::String(ref value) => value.clone(),
+                serde_json::Value::Null => String::new(),
+                ref value => value.to_string(),
+            };
+            (parameter.name.clone(), value)
+        })
+        .collect()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JTestCase {
+    class_name: String,
+    name: String,
+    status: String,
+}
+
+impl JTestCase {
+    fn is_failure(&self) -> bool {
+        self.status == "FAILED" || self.status == "REGRESSION"
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct JTestSuite {
+    #[serde(default)]
+    cases: Vec<JTestCase>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct JTestReport {
+    #[serde(default)]
+    suites: Vec<JTestSuite>,
+}
+
+impl JTestReport {
+    fn failures(self, max: usize) -> Vec<String> {
+        self.suites
+            .into_iter()
+            .flat_map(|suite| suite.cases)
+            .filter(JTestCase::is_failure)
+            .take(max)
+            .map(|case| format!("{}.{}", case.class_name, case.name))
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JStage {
+    name: String,
+    status: String,
+    #[serde(default)]
+    duration_millis: u32,
+}
+
+/// The description of a Pipeline build by the Pipeline REST API.
+#[derive(Deserialize, Debug, Clone)]
+struct JPipeline {
+    #[serde(default)]
+    stages: Vec<JStage>,
+}
+
+impl JPipeline {
+    fn failed_stages(self) -> Vec<Stage> {
+        self.stages
+            .into_iter()
+            .filter_map(|stage| {
+                let status = match stage.status.as_str() {
+                    "FAILED" => "failed",
+                    "UNSTABLE" => "unstable",
+                    _ => return None,
+                };
+                Some(Stage {
+                    name: stage.name,
+                    status: status.to_string(),
+                    duration: BuildDuration(stage.duration_millis),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JBuildDetails {
+    #[serde(default)]
+    culprits: Vec<JUser>,
+    /// Some actions may be null
+    #[serde(default)]
+    actions: Vec<Option<JAction>>,
+    /// Freestyle jobs have a single change set
+    change_set: Option<JChangeSet>,
+    /// Pipeline jobs have one change set per checkout
+    #[serde(default)]
+    change_sets: Vec<JChangeSet>,
+}
+
+impl JBuildDetails {
+    fn changes(self) -> impl Iterator<Item = JChange> {
+        self.change_set
+            .into_iter()
+            .chain(self.change_sets)
+            .flat_map(|change_set| change_set.items)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JStartDetails {
+    /// In milliseconds, or -1 if Jenkins has no estimate
+    estimated_duration: Option<i64>,
+    /// Some actions may be null
+    #[serde(default)]
+    actions: Vec<Option<JAction>>,
+}
+
+/// The name used to mention `user` on IRC: their nick if it is known, their full name
+/// otherwise.
+fn mention(config: &Config, user: &JUser, email: Option<&str>) -> String {
+    nick(config, user, email)
+        .unwrap_or(&user.full_name)
+        .to_string()
+}
+
+/// The IRC nick of `user`, looked up by Jenkins user id, then by commit author email.
+fn nick<'a>(config: &'a Config, user: &JUser, email: Option<&str>) -> Option<&'a str> {
+    user.id
+        .as_deref()
+        .and_then(|id| config.nick(id))
+        .or_else(|| email.and_then(|email| config.nick(email)))
+}
+
+/// Fetch the causes and parameters of `build`, and if it failed, its culprits, changes and
+/// test results.
+fn attempt(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    build: &BuildEvent,
+) -> Result<JBuildDetails, Error> {
+    let tree = if build.result.is_success() {
+        format!("actions[{}]", ACTION_TREE)
+    } else {
+        let changes = format!("items[msg,authorEmail,author[{}]]", USER_TREE);
+        format!(
+            "culprits[{users}],changeSet[{changes}],changeSets[{changes}],\
+             actions[{actions},failCount,skipCount,totalCount]",
+            users = USER_TREE,
+            changes = changes,
+            actions = ACTION_TREE
+        )
+    };
+    get(client, j_config, &build.url, "", &tree)
+}
+
+/// GET the JSON API of the build at `url`, or of one of its sub-pages such as `testReport/`.
+fn get<T>(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    url: &BuildUrl,
+    page: &str,
+    tree: &str,
+) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let url = format!("{}/{}api/json", url.0.trim_end_matches('/'), page);
+    client
+        .get(url)
+        .query(&[("tree", tree)])
+        .basic_auth(&j_config.user, Some(&j_config.token))
+        .send()?
+        .json()
+}
+
+fn failed_tests(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    build: &BuildEvent,
+    max: usize,
+) -> Result<Vec<String>, Error> {
+    let report: JTestReport = get(
+        client,
+        j_config,
+        &build.url,
+        "testReport/",
+        "suites[cases[className,name,status]]",
+    )?;
+    Ok(report.failures(max))
+}
+
+/// The failed stages of `build`, or `None` if it is not a Pipeline build.
+fn failed_stages(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    build: &BuildEvent,
+) -> Result<Option<Vec<Stage>>, Error> {
+    let url = format!("{}/wfapi/describe", build.url.0.trim_end_matches('/'));
+    let response = client
+        .get(url)
+        .basic_auth(&j_config.user, Some(&j_config.token))
+        .send()?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let pipeline: JPipeline = response.error_for_status()?.json()?;
+    Ok(Some(pipeline.failed_stages()))
+}
+
+/// Add the causes and parameters of `build` to it, and if it failed, its culprits, changes,
+/// test results and failed Pipeline stages. If `pm_culprits` is set, the people involved
+/// whose nick is known are added to the destinations. Nothing is fetched for a successful
+/// build whose template shows neither its causes nor its parameters.
+pub fn fetch(client: &Client, config: &Config, j_config: &JenkinsConfig, build: &mut BuildEvent) {
+    let needs_causes = match config.template(&build.server, &build.name.0, build.transition) {
+        Some(template) => template.uses_causes_or_parameters(),
+        None => Template::builtin(build.transition).uses_causes_or_parameters(),
+    };
+    if build.result.is_success() && !needs_causes {
+        return;
+    }
+    match attempt(client, j_config, build) {
+        Ok(details) => apply(config, j_config, details, build),
+        Err(err) => error!(
+            "Could not fetch details of build #{} of job {}: {}",
+            build.number, build.name, err
+        ),
+    }
+    if build.result.is_success() {
+        return;
+    }
+    let has_failed_tests = build.details.tests.is_some_and(|tests| tests.failed > 0);
+    if has_failed_tests && config.max_failed_tests > 0 {
+        match failed_tests(client, j_config, build, config.max_failed_tests) {
+            Ok(names) => build.details.failed_tests = names,
+            Err(err) => error!(
+                "Could not fetch test report of build #{} of job {}: {}",
+                build.number, build.name, err
+            ),
+        }
+    }
+    match failed_stages(client, j_config, build) {
+        Ok(Some(stages)) => build.details.failed_stages = stages,
+        Ok(None) => debug!("Job {} is not a Pipeline job", build.name),
+        Err(err) => error!(
+            "Could not fetch stages of build #{} of job {}: {}",
+            build.number, build.name, err
+        ),
+    }
+}
+
+/// Add the causes and the estimated duration of a build that has just started to `start`.
+pub fn fetch_start(client: &Client, j_config: &JenkinsConfig, start: &mut StartEvent) {
+    let tree = "estimatedDuration,actions[causes[shortDescription]]";
+    match get::<JStartDetails>(client, j_config, &start.url, "", tree) {
+        Ok(details) => {
+            start.causes = causes(&details.actions);
+            start.estimated_duration = details
+                .estimated_duration
+                .filter(|duration| *duration >= 0)
+                .map(|duration| BuildDuration(duration as u32));
+        }
+        Err(err) => error!(
+            "Could not fetch details of running build #{} of job {}: {}",
+            start.number, start.name, err
+        ),
+    }
+}
+
+fn apply(
+    config: &Config,
+    j_config: &JenkinsConfig,
+    details: JBuildDetails,
+    build: &mut BuildEvent,
+) {
+    build.details.causes.extend(causes(&details.actions));
+    build.details.parameters = parameters(&details.actions);
+    build.details.tests = details
+        .actions
+        .iter()
+        .flatten()
+        .find_map(JAction::test_counts);
+    let mut nicks: Vec<&str> = details
+        .culprits
+        .iter()
+        .filter_map(|user| nick(config, user, None))
+        .collect();
+    build.details.culprits = details
+        .culprits
+        .iter()
+        .map(|user| mention(config, user, None))
+        .collect();
+    let changes: Vec<JChange> = details.changes().collect();
+    changes.iter().for_each(|change| {
+        let email = change.author_email.as_deref();
+        if let Some(nick) = change
+            .author
+            .as_ref()
+            .and_then(|user| nick(config, user, email))
+        {
+            nicks.push(nick);
+        }
+    });
+    build.details.changes = changes
+        .iter()
+        .take(config.max_changes)
+        .map(|change| Change {
+            author: change
+                .author
+                .as_ref()
+                .map(|user| mention(config, user, change.author_email.as_deref()))
+                .unwrap_or_default(),
+            message: change.msg.lines().next().unwrap_or("").to_string(),
+        })
+        .collect();
+    let failed = build.result == BuildResult::Failure || build.result == BuildResult::Unstable;
+    if j_config.pm_culprits && failed {
+        nicks.into_iter().for_each(|nick| {
+            if !build.notify.iter().any(|dest| dest == nick) {
+                build.notify.push(nick.to_string());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::event::tests::build_event;
+    use super::*;
+
+    #[test]
+    fn apply_culprits_and_changes() {
+        let toml = r##"
+sleep = 60
+max_changes = 2
+
+[users]
+jdoe = "johnny"
+"Alice@Example.com" = "alice"
+
+[[job]]
+server = "https://jenkins.example.com"
+id = "jenkins"
+user = "carlo"
+token = "secret"
+notify = ["#chan"]
+pm_culprits = true
+"##;
+        let config = Config::from_string(toml).unwrap();
+        let json = r#"{
+            "culprits": [{"id": "jdoe", "fullName": "John Doe"},
+                         {"id": "asmith", "fullName": "Alice Smith"}],
+            "changeSets": [{"items": [
+                {"msg": "Fix the build\n\nReally",
+                 "author": {"id": "jdoe", "fullName": "John Doe"}},
+                {"msg": "Break it again", "authorEmail": "alice@example.com",
+                 "author": {"id": "asmith", "fullName": "Alice Smith"}},
+                {"msg": "Too many changes", "author": null}
+            ]}],
+            "actions": [null, {}, {"failCount": 12, "skipCount": 3, "totalCount": 3400}]
+        }"#;
+        let details: JBuildDetails = serde_json::from_str(json).unwrap();
+        let mut build = build_event();
+        build.result = BuildResult::Aborted;
+        apply(&config, &config.job[0], details, &mut build);
+        assert_eq!(build.notify, vec!["#chan"]);
+
+        let details: JBuildDetails = serde_json::from_str(json).unwrap();
+        let mut build = build_event();
+        apply(&config, &config.job[0], details, &mut build);
+        assert_eq!(build.details.culprits, vec!["johnny", "Alice Smith"]);
+        assert_eq!(build.notify, vec!["#chan", "johnny", "alice"]);
+        assert_eq!(
+            build.details.tests,
+            Some(TestCounts {
+                failed: 12,
+                skipped: 3,
+                total: 3400
+            })
+        );
+        assert_eq!(
+            build.details.changes,
+            vec![
+                Change {
+                    author: String::from("johnny"),
+                    message: String::from("Fix the build"),
+                },
+                Change {
+                    author: String::from("alice"),
+                    message: String::from("Break it again"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn read_causes_and_parameters() {
+        let json = r#"{"actions": [
+            null, {}, {"_class": "hudson.model.CauseAction",
+                       "causes": [{"shortDescription": "Started by timer"},
+                                  {"shortDescription": "Started by timer"},
+                                  {"shortDescription": "Started by user Alice"}]},
+            {"parameters": [{"name": "BRANCH", "value": "main"},
+                            {"name": "CLEAN", "value": true},
+                            {"name": "PASSWORD"}]}]}"#;
+        let details: JStartDetails = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            causes(&details.actions),
+            vec!["Started by timer", "Started by user Alice"]
+        );
+        let expected = [("BRANCH", "main"), ("CLEAN", "true"), ("PASSWORD", "")];
+        let expected: Vec<(String, String)> = expected
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(parameters(&details.actions), expected);
+    }
+
+    #[test]
+    fn list_failed_stages() {
+        let json = r#"{"id": "12", "status": "FAILED", "stages": [
+            {"name": "build", "status": "SUCCESS", "durationMillis": 60000},
+            {"name": "unit-tests", "status": "UNSTABLE", "durationMillis": 30000},
+            {"name": "integration-tests", "status": "FAILED", "durationMillis": 720000},
+            {"name": "deploy", "status": "NOT_EXECUTED", "durationMillis": 0}
+        ]}"#;
+        let pipeline: JPipeline = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            pipeline.failed_stages(),
+            vec![
+                Stage {
+                    name: String::from("unit-tests"),
+                    status: String::from("unstable"),
+                    duration: BuildDuration(30_000),
+                },
+                Stage {
+                    name: String::from("integration-tests"),
+                    status: String::from("failed"),
+                    duration: BuildDuration(720_000),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn list_failed_tests() {
+        let json = r#"{"suites": [
+            {"cases": [{"className": "a.B", "name": "ok", "status": "PASSED"},
+                       {"className": "a.B", "name": "broken", "status": "REGRESSION"}]},
+            {"cases": [{"className": "c.D", "name": "still", "status": "FAILED"},
+                       {"className": "c.D", "name": "skipped", "status": "SKIPPED"},
+                       {"className": "c.D", "name": "other", "status": "FAILED"}]}
+        ]}"#;
+        let report: JTestReport = serde_json::from_str(json).unwrap();
+        assert_eq!(report.failures(2), vec!["a.B.broken", "c.D.still"]);
+    }
+}
@@ -0,0 +1,279 @@
+use std::fmt;
+
+use super::cache::{Name, Timestamp};
+use super::{BuildDuration, BuildNumber, BuildResult, BuildUrl, Transition};
+
+/// A completed build, with everything the notification targets may want to know about it.
+#[derive(Debug, Clone)]
+pub struct BuildEvent {
+    /// The `id` of the Jenkins server the job lives on
+    pub server: String,
+    pub name: Name,
+    pub number: BuildNumber,
+    pub result: BuildResult,
+    pub transition: Transition,
+    pub duration: BuildDuration,
+    pub url: BuildUrl,
+    /// Channels and nicks that should hear about this build
+    pub notify: Vec<String>,
+    pub details: BuildDetails,
+    /// When the build started, if its start was announced
+    pub started: Option<Timestamp>,
+}
+
+/// A build that has just started, for jobs whose starts are announced.
+#[derive(Debug, Clone)]
+pub struct StartEvent {
+    /// The `id` of the Jenkins server the job lives on
+    pub server: String,
+    pub name: Name,
+    pub number: BuildNumber,
+    pub url: BuildUrl,
+    pub notify: Vec<String>,
+    /// Why the build was started
+    pub causes: Vec<String>,
+    /// How long Jenkins expects the build to take, if it knows
+    pub estimated_duration: Option<BuildDuration>,
+}
+
+impl fmt::Display for StartEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Build #{} for job '{}' on '{}' has started",
+            self.number, self.name, self.server
+        )?;
+        if !self.causes.is_empty() {
+            write!(f, " ({})", self.causes.join(", "))?;
+        }
+        if let Some(duration) = self.estimated_duration {
+            write!(f, ", estimated duration: {}", duration)?;
+        }
+        write!(f, ", URL: {}", self.url)
+    }
+}
+
+/// A build aborted from IRC.
+#[derive(Debug, Clone)]
+pub struct AbortEvent {
+    /// The `id` of the Jenkins server the job lives on
+    pub server: String,
+    pub name: Name,
+    pub number: BuildNumber,
+    /// Who aborted the build
+    pub nick: String,
+    /// Where the abort was requested
+    pub reply_to: String,
+}
+
+/// The time of day of `timestamp`, as `HH:MM UTC`.
+pub fn time_of_day(timestamp: Timestamp) -> String {
+    let minutes = timestamp.0 / 60_000 % (24 * 60);
+    format!("{:02}:{:02} UTC", minutes / 60, minutes % 60)
+}
+
+/// Additional information about a build, when Jenkins provides it. The causes and the
+/// parameters are only shown by templates that ask for them.
+#[derive(Debug, Clone, Default)]
+pub struct BuildDetails {
+    /// Users who may have broken the build
+    pub culprits: Vec<String>,
+    /// Who aborted the build from IRC
+    pub aborted_by: Option<String>,
+    pub causes: Vec<String>,
+    pub parameters: Vec<(String, String)>,
+    pub changes: Vec<Change>,
+    pub tests: Option<TestCounts>,
+    /// Names of the first failed test cases
+    pub failed_tests: Vec<String>,
+    /// Failed or unstable stages of Pipeline builds
+    pub failed_stages: Vec<Stage>,
+    /// Excerpt of the console output, posted after the notification
+    pub console: Vec<String>,
+}
+
+impl fmt::Display for BuildDetails {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(ref nick) = self.aborted_by {
+            parts.push(format!("aborted by {}", nick));
+        }
+        if !self.failed_stages.is_empty() {
+            let stages: Vec<String> = self.failed_stages.iter().map(Stage::to_string).collect();
+            parts.push(stages.join(", "));
+        }
+        if !self.changes.is_empty() {
+            let changes: Vec<String> = self.changes.iter().map(Change::to_string).collect();
+            parts.push(changes.join(", "));
+        }
+        if let Some(ref tests) = self.tests {
+            if self.failed_tests.is_empty() {
+                parts.push(tests.to_string());
+            } else {
+                parts.push(format!("{}: {}", tests, self.failed_tests.join(", ")));
+            }
+        }
+        parts.join("; ").fmt(f)
+    }
+}
+
+/// A commit that went into a build.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub author: String,
+    pub message: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.author, self.message)
+    }
+}
+
+/// A stage of a Pipeline build that did not succeed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stage {
+    pub name: String,
+    /// `failed` or `unstable`
+    pub status: String,
+    pub duration: BuildDuration,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at stage '{}' after {}",
+            self.status,
+            self.name,
+            short_duration(self.duration)
+        )
+    }
+}
+
+/// Format `duration` as e.g. `1h05m`, `12m` or `40s`.
+fn short_duration(duration: BuildDuration) -> String {
+    let seconds = duration.0 / 1000;
+    match (seconds / 3600, seconds / 60 % 60) {
+        (0, 0) => format!("{}s", seconds),
+        (0, minutes) => format!("{}m", minutes),
+        (hours, minutes) => format!("{}h{:02}m", hours, minutes),
+    }
+}
+
+/// Summary of the test report of a build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestCounts {
+    pub failed: u32,
+    pub skipped: u32,
+    pub total: u32,
+}
+
+impl fmt::Display for TestCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed / {} tests", self.failed, self.total)?;
+        if self.skipped > 0 {
+            write!(f, " ({} skipped)", self.skipped)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub fn build_event() -> BuildEvent {
+        BuildEvent {
+            server: String::from("jenkins"),
+            name: Name(String::from("nightly")),
+            number: BuildNumber(42),
+            result: BuildResult::Failure,
+            transition: Transition::Broken,
+            duration: BuildDuration(90_000),
+            url: BuildUrl(String::from("http://jenkins/job/nightly/42/")),
+            notify: vec![String::from("#chan")],
+            details: BuildDetails::default(),
+            started: None,
+        }
+    }
+
+    #[test]
+    fn display_start() {
+        let mut start = StartEvent {
+            server: String::from("jenkins"),
+            name: Name(String::from("release")),
+            number: BuildNumber(7),
+            url: BuildUrl(String::from("http://jenkins/job/release/7/")),
+            notify: vec![String::from("#chan")],
+            causes: Vec::new(),
+            estimated_duration: None,
+        };
+        assert_eq!(
+            start.to_string(),
+            "Build #7 for job 'release' on 'jenkins' has started, \
+             URL: http://jenkins/job/release/7/"
+        );
+        start.causes.push(String::from("Started by user Alice"));
+        start.estimated_duration = Some(BuildDuration(720_000));
+        assert_eq!(
+            start.to_string(),
+            "Build #7 for job 'release' on 'jenkins' has started (Started by user Alice), \
+             estimated duration: 720 s, URL: http://jenkins/job/release/7/"
+        );
+    }
+
+    #[test]
+    fn display_stages() {
+        let stage = |name: &str, status: &str, duration| Stage {
+            name: name.to_string(),
+            status: status.to_string(),
+            duration: BuildDuration(duration),
+        };
+        let mut details = BuildDetails {
+            failed_stages: vec![
+                stage("build", "unstable", 40_000),
+                stage("integration-tests", "failed", 720_000),
+            ],
+            ..BuildDetails::default()
+        };
+        details.failed_tests.push(String::from("a.B.broken"));
+        assert_eq!(
+            details.to_string(),
+            "unstable at stage 'build' after 40s, \
+             failed at stage 'integration-tests' after 12m"
+        );
+        assert_eq!(short_duration(BuildDuration(3_900_000)), "1h05m");
+    }
+
+    #[test]
+    fn format_time_of_day() {
+        assert_eq!(time_of_day(Timestamp(0)), "00:00 UTC");
+        assert_eq!(time_of_day(Timestamp(1_600_000_000_000)), "12:26 UTC");
+    }
+
+    #[test]
+    fn display_details() {
+        let mut details = BuildDetails::default();
+        assert_eq!(details.to_string(), "");
+
+        details.causes.push(String::from("Started by timer"));
+        details
+            .parameters
+            .push((String::from("BRANCH"), String::from("main")));
+        assert_eq!(details.to_string(), "");
+
+        details.tests = Some(TestCounts {
+            failed: 12,
+            skipped: 3,
+            total: 3400,
+        });
+        assert_eq!(details.to_string(), "12 failed / 3400 tests (3 skipped)");
+
+        details.failed_tests = vec![String::from("a.B.broken"), String::from("c.D.still")];
+        assert_eq!(
+            details.to_string(),
+            "12 failed / 3400 tests (3 skipped): a.B.broken, c.D.still"
+        );
+    }
+}
@@ -1,18 +1,30 @@
 pub mod cache;
+mod console;
+mod details;
+pub mod event;
+mod queue;
+pub mod remote;
 
-use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt;
-use std::sync::mpsc::Sender;
+use std::iter;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use reqwest::Error;
 use reqwest::blocking::Client;
+use reqwest::Error;
+use serde::{Deserialize, Deserializer};
 
 use crate::carlo::Event;
 use crate::config::{Config, JenkinsConfig};
 
-#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+use self::event::{BuildDetails, BuildEvent, StartEvent};
+
+#[derive(
+    Deserialize, Serialize, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy,
+)]
 pub struct BuildNumber(pub u32);
 
 impl fmt::Display for BuildNumber {
@@ -30,8 +42,8 @@ impl fmt::Display for BuildDuration {
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
-pub struct BuildUrl(String);
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct BuildUrl(pub String);
 
 impl fmt::Display for BuildUrl {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -39,142 +51,814 @@ impl fmt::Display for BuildUrl {
     }
 }
 
+/// The outcome of a completed build, as reported by Jenkins.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Hash, Clone)]
+#[serde(from = "String", into = "String")]
+pub enum BuildResult {
+    Success,
+    Unstable,
+    Failure,
+    Aborted,
+    NotBuilt,
+    Unknown(String),
+}
+
+impl BuildResult {
+    pub fn is_success(&self) -> bool {
+        *self == BuildResult::Success
+    }
+
+    /// Whether the build did not run to completion, so that its result says nothing about the
+    /// state of the job.
+    pub fn is_interrupted(&self) -> bool {
+        *self == BuildResult::Aborted || *self == BuildResult::NotBuilt
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            BuildResult::Success => "SUCCESS",
+            BuildResult::Unstable => "UNSTABLE",
+            BuildResult::Failure => "FAILURE",
+            BuildResult::Aborted => "ABORTED",
+            BuildResult::NotBuilt => "NOT_BUILT",
+            BuildResult::Unknown(result) => result,
+        }
+    }
+}
+
+impl From<&str> for BuildResult {
+    fn from(result: &str) -> BuildResult {
+        match result.to_ascii_uppercase().as_str() {
+            "SUCCESS" => BuildResult::Success,
+            "UNSTABLE" => BuildResult::Unstable,
+            "FAILURE" => BuildResult::Failure,
+            "ABORTED" => BuildResult::Aborted,
+            "NOT_BUILT" => BuildResult::NotBuilt,
+            _ => BuildResult::Unknown(result.to_string()),
+        }
+    }
+}
+
+impl From<String> for BuildResult {
+    fn from(result: String) -> BuildResult {
+        BuildResult::from(result.as_str())
+    }
+}
+
+impl From<BuildResult> for String {
+    fn from(result: BuildResult) -> String {
+        result.as_str().to_string()
+    }
+}
+
+impl fmt::Display for BuildResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+/// How the result of a build compares to the result of the previous build of the same job.
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Transition {
+    Broken,
+    StillFailing,
+    Fixed,
+    StillGreen,
+    /// The build was aborted or not built, which neither breaks nor fixes the job
+    Interrupted,
+}
+
+impl Transition {
+    /// Compare `current` to `previous`, the result of the last build of the job that was not
+    /// interrupted.
+    pub fn between(previous: Option<&BuildResult>, current: &BuildResult) -> Transition {
+        if current.is_interrupted() {
+            return Transition::Interrupted;
+        }
+        let was_green = previous
+            .filter(|previous| !previous.is_interrupted())
+            .map(BuildResult::is_success);
+        match (was_green, current.is_success()) {
+            (Some(false), true) => Transition::Fixed,
+            (Some(false), false) => Transition::StillFailing,
+            (_, true) => Transition::StillGreen,
+            (_, false) => Transition::Broken,
+        }
+    }
+
+    pub fn is_change(self) -> bool {
+        self == Transition::Broken || self == Transition::Fixed
+    }
+}
+
+impl fmt::Display for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Transition::Broken => "broken",
+            Transition::StillFailing => "still failing",
+            Transition::Fixed => "fixed",
+            Transition::StillGreen => "still green",
+            Transition::Interrupted => "interrupted",
+        }
+        .fmt(f)
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct JBuild {
-    pub result: Option<String>,
+    pub result: Option<BuildResult>,
     pub timestamp: cache::Timestamp,
     pub number: BuildNumber,
     pub duration: BuildDuration,
     pub url: BuildUrl,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(rename_all = "camelCase")]
+/// A job that has been built at least once, named after its full path in the folder
+/// hierarchy (e.g. `team/project/main`).
+#[derive(Debug, Clone)]
 pub struct JJob {
     pub name: cache::Name,
     pub last_build: JBuild,
+    pub last_completed_build: Option<JBuild>,
+    pub builds: Vec<JBuild>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-struct JJobVec(Vec<JJob>);
+/// An element of a Jenkins `jobs` list: a job, or a folder (including organization folders
+/// and multibranch pipelines) containing more items.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+struct JItem {
+    name: String,
+    #[serde(rename = "_class", default)]
+    class: Option<String>,
+    /// `None` if the item has no builds at all, `Some(None)` if it has never been built
+    #[serde(default, deserialize_with = "present")]
+    last_build: Option<Option<JBuild>>,
+    #[serde(default)]
+    last_completed_build: Option<JBuild>,
+    #[serde(default)]
+    builds: Vec<JBuild>,
+    #[serde(default)]
+    buildable: Option<bool>,
+    #[serde(default)]
+    color: Option<String>,
+    /// `None` if the item is not a folder
+    #[serde(default, deserialize_with = "tolerant_items")]
+    jobs: Option<Vec<JItem>>,
+    /// Set on the items that could not be understood, of which only the name is known
+    #[serde(skip)]
+    unreadable: bool,
+}
+
+/// Deserialize a field that may be null, distinguishing it from a missing field.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Deserialize a list of items one at a time, replacing the items that cannot be understood
+/// by unreadable items carrying only their name, instead of failing altogether.
+fn tolerant_items<'de, D>(deserializer: D) -> Result<Option<Vec<JItem>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values = Option::<Vec<serde_json::Value>>::deserialize(deserializer)?;
+    Ok(values.map(|values| {
+        values
+            .into_iter()
+            .filter_map(|value| {
+                let name = value
+                    .get("name")
+                    .and_then(|name| name.as_str())
+                    .map(String::from);
+                let description = name
+                    .clone()
+                    .or_else(|| value.get("_class").map(|class| class.to_string()))
+                    .unwrap_or_else(|| String::from("<unnamed>"));
+                serde_json::from_value(value)
+                    .map_err(|err| warn!("Skipping item {}: {}", description, err))
+                    .ok()
+                    .or_else(|| {
+                        name.map(|name| JItem {
+                            name,
+                            unreadable: true,
+                            ..JItem::default()
+                        })
+                    })
+            })
+            .collect()
+    }))
+}
+
+impl JItem {
+    fn is_disabled(&self) -> bool {
+        self.buildable == Some(false) || self.color.as_deref() == Some("disabled")
+    }
+
+    /// Collect the jobs among `items` into `job_vec`, descending at most `depth` levels of
+    /// folders. The items that are skipped are recorded in `job_vec` too.
+    fn collect(items: Vec<JItem>, prefix: &str, depth: usize, job_vec: &mut JJobVec) {
+        items.into_iter().for_each(|item| {
+            let path = if prefix.is_empty() {
+                item.name.clone()
+            } else {
+                format!("{}/{}", prefix, item.name)
+            };
+            let disabled = item.is_disabled();
+            match (item.last_build, item.jobs) {
+                _ if item.unreadable => (),
+                (Some(Some(_)), _) if disabled => info!("Job {} is disabled", path),
+                (Some(Some(last_build)), _) => {
+                    job_vec.0.push(JJob {
+                        name: cache::Name(path),
+                        last_build,
+                        last_completed_build: item.last_completed_build,
+                        builds: item.builds,
+                    });
+                    return;
+                }
+                (Some(None), _) => info!("Job {} has never been built", path),
+                (None, Some(_)) if depth == 0 => {
+                    info!("Not descending into folder {}: maximum depth reached", path)
+                }
+                (None, Some(items)) => return JItem::collect(items, &path, depth - 1, job_vec),
+                (None, None) => warn!(
+                    "Skipping item {} of class {}: it is neither a job nor a folder",
+                    path,
+                    item.class.as_deref().unwrap_or("<unknown>")
+                ),
+            }
+            job_vec.1.push(cache::Name(path));
+        });
+    }
+}
+
+/// The jobs of a server, and the names of the items that were skipped. The skipped items
+/// (and the jobs of skipped folders) are still there, so what is known about them is kept.
+#[derive(Debug, Clone, Default)]
+struct JJobVec(Vec<JJob>, Vec<cache::Name>);
 
 #[derive(Deserialize, Debug, Clone)]
 struct JJson {
-    jobs: JJobVec,
+    #[serde(deserialize_with = "tolerant_items")]
+    jobs: Option<Vec<JItem>>,
+}
+
+impl JJson {
+    fn into_jobs(self, depth: usize) -> JJobVec {
+        let mut job_vec = JJobVec::default();
+        JItem::collect(self.jobs.unwrap_or_default(), "", depth, &mut job_vec);
+        job_vec
+    }
+}
+
+/// Fields of builds needed by the poller
+const BUILD_TREE: &str = "number,result,timestamp,duration,url";
+
+/// Number of builds fetched in addition to `max_builds`, to account for running builds
+const HISTORY_MARGIN: usize = 5;
+
+/// Return the `tree` query parameter selecting the fields needed by the poller, for jobs
+/// in folders nested at most `depth` times, with up to `history` builds per job.
+fn jobs_tree(depth: usize, history: usize) -> String {
+    let item_tree = format!(
+        "_class,name,buildable,color,lastBuild[{build}],lastCompletedBuild[{build}],\
+         builds[{build}]{{0,{history}}}",
+        build = BUILD_TREE,
+        history = history
+    );
+    let mut tree = format!("jobs[{}]", item_tree);
+    (0..depth).for_each(|_| tree = format!("jobs[{},{}]", item_tree, tree));
+    tree
 }
 
 #[derive(Debug)]
 pub struct JListener {
     tx: Sender<Event>,
-    most_recent: cache::Cache,
+    /// Shared with the IRC commands that report what is known about the jobs
+    most_recent: Arc<Mutex<cache::Cache>>,
+    /// When the console output of each job was last posted, by server id and job name
+    console_posted: HashMap<(String, cache::Name), Instant>,
+    /// Number of console excerpts posted during the current poll
+    console_excerpts: usize,
+    queue_monitor: queue::QueueMonitor,
+    /// Set during the first poll, whose events are not sent
+    warming_up: bool,
 }
 
 impl JListener {
-    pub fn new(tx: Sender<Event>) -> JListener {
+    pub fn new(tx: Sender<Event>, most_recent: Arc<Mutex<cache::Cache>>) -> JListener {
         JListener {
             tx,
-            most_recent: cache::Cache::new(),
+            most_recent,
+            console_posted: HashMap::new(),
+            console_excerpts: 0,
+            queue_monitor: queue::QueueMonitor::new(),
+            warming_up: false,
         }
     }
 
-    fn attempt(&self, client: &Client, j_config: &JenkinsConfig) -> Result<JJson, Error> {
+    fn attempt(
+        &self,
+        client: &Client,
+        j_config: &JenkinsConfig,
+        max_builds: usize,
+    ) -> Result<JJson, Error> {
         info!(
             "Attempting connection to \"{}\" ({}) as {}",
             j_config.id, j_config.server, j_config.user
         );
-        let response = client
-            .get(&j_config.server)
+        let request = if j_config.is_api_url() {
+            client.get(&j_config.server)
+        } else {
+            let tree = jobs_tree(j_config.depth, max_builds + HISTORY_MARGIN);
+            client
+                .get(format!("{}/api/json", j_config.view_url()))
+                .query(&[("tree", tree)])
+        };
+        let response = request
             .basic_auth(&j_config.user, Some(&j_config.token))
             .send()?;
         response.json()
     }
 
-    fn prune_missing_builds(&mut self, job_vec: &Vec<JJob>, j_config: &JenkinsConfig) {
-        let mut build_names = Vec::new() as Vec<&cache::Name>;
-        job_vec
+    /// Forget the jobs that are not part of `job_vec` anymore. The skipped items of
+    /// `job_vec`, and the jobs in skipped folders, are kept.
+    fn prune_missing_builds(&mut self, job_vec: &JJobVec, j_config: &JenkinsConfig) {
+        let mut most_recent = self.most_recent.lock().unwrap();
+        let skipped: Vec<cache::Name> = most_recent
+            .entries()
+            .filter(|(server, name, _)| {
+                **server == j_config.id
+                    && job_vec.1.iter().any(|skipped| {
+                        name.0 == skipped.0 || name.0.starts_with(&format!("{}/", skipped))
+                    })
+            })
+            .map(|(_, name, _)| name.clone())
+            .collect();
+        let build_names: Vec<&cache::Name> = job_vec
+            .0
             .iter()
-            .for_each(|ref job| build_names.push(&job.name));
+            .map(|job| &job.name)
+            .chain(skipped.iter())
+            .collect();
         info!(
             "Will keep {} builds for server {}",
             build_names.len(),
             j_config.id
         );
-        self.most_recent
-            .prune_except(&j_config.server, &build_names);
+        most_recent.prune_except(&j_config.id, &build_names);
     }
 
-    fn update_builds(&mut self, job_vec: Vec<JJob>, j_config: &JenkinsConfig) -> Vec<Event> {
-        let mut events = Vec::new();
-        job_vec
+    /// Split the known builds of `job` into the completed builds that must be reported, oldest
+    /// first, and a cache entry describing the last completed build. Builds are reported if they
+    /// are more recent than the last completed build in `previous`, or if they were still running
+    /// when it was recorded. If the job has never been seen before, only its most recent
+    /// completed build is reported.
+    fn completed_builds_since(
+        job: JJob,
+        previous: Option<&cache::Entry>,
+    ) -> (Vec<JBuild>, Option<cache::Entry>) {
+        let mut builds = if job.builds.is_empty() {
+            job.last_completed_build
+                .into_iter()
+                .chain(iter::once(job.last_build))
+                .collect()
+        } else {
+            job.builds
+        };
+        builds.sort_by_key(|build| build.number);
+        builds.dedup_by_key(|build| build.number);
+        let (mut completed, running): (Vec<JBuild>, Vec<JBuild>) =
+            builds.into_iter().partition(|build| build.result.is_some());
+
+        let mut latest = match completed.last() {
+            Some(latest) => cache::Entry {
+                last_completed: latest.number,
+                timestamp: latest.timestamp,
+                result: latest.result.clone(),
+                pending: running
+                    .iter()
+                    .map(|build| build.number)
+                    .filter(|number| *number < latest.number)
+                    .collect(),
+                started: Vec::new(),
+                url: Some(latest.url.clone()),
+                broken_since: None,
+                decisive_result: None,
+            },
+            None => {
+                info!("Job {} has no completed build yet", job.name);
+                return (Vec::new(), None);
+            }
+        };
+        let previous = match previous {
+            Some(entry) if latest.last_completed < entry.last_completed => {
+                warn!(
+                    "Job {} went back in time from build #{} to #{}",
+                    job.name, entry.last_completed, latest.last_completed
+                );
+                None
+            }
+            previous => previous,
+        };
+        latest.decisive_result = completed
+            .iter()
+            .rev()
+            .filter_map(|build| build.result.clone())
+            .find(|result| !result.is_interrupted())
+            .or_else(|| previous.and_then(|entry| entry.outcome().cloned()));
+        latest.broken_since = Self::broken_since(&completed, previous);
+        match previous {
+            Some(entry) => completed.retain(|build| {
+                build.number > entry.last_completed || entry.pending.contains(&build.number)
+            }),
+            None => {
+                let first_recent = completed.len() - 1;
+                completed.drain(..first_recent);
+            }
+        }
+        (completed, Some(latest))
+    }
+
+    /// When the current series of unsuccessful builds started, given the completed builds of a
+    /// job, oldest first, and its entry in the cache. Returns `None` if the last completed
+    /// build is successful, or if the series started before the oldest known build.
+    /// Interrupted builds are ignored.
+    fn broken_since(
+        completed: &[JBuild],
+        previous: Option<&cache::Entry>,
+    ) -> Option<cache::Timestamp> {
+        let completed: Vec<JBuild> = completed
+            .iter()
+            .filter(|build| build.result.as_ref().is_some_and(|r| !r.is_interrupted()))
+            .cloned()
+            .collect();
+        let is_success = |build: &JBuild| build.result.as_ref().is_some_and(|r| r.is_success());
+        if completed.last().is_none_or(is_success) {
+            return None;
+        }
+        let new = match previous {
+            Some(entry) => {
+                let first_new = completed.partition_point(|b| b.number <= entry.last_completed);
+                &completed[first_new..]
+            }
+            None => &completed[..],
+        };
+        if let Some(last_success) = new.iter().rposition(is_success) {
+            return Some(new[last_success + 1].timestamp);
+        }
+        match previous {
+            // the series was already going on
+            Some(entry) if entry.outcome().is_some_and(|r| !r.is_success()) => entry.broken_since,
+            Some(_) => new.first().map(|build| build.timestamp),
+            // a job seen for the first time, whose builds have all failed
+            None => completed
+                .first()
+                .filter(|build| build.number == BuildNumber(1))
+                .map(|build| build.timestamp),
+        }
+    }
+
+    /// The builds of `job` that are still running, oldest first.
+    fn running_builds(job: &JJob) -> Vec<JBuild> {
+        let mut running: Vec<JBuild> = job
+            .builds
+            .iter()
+            .chain(iter::once(&job.last_build))
+            .filter(|build| build.result.is_none())
+            .cloned()
+            .collect();
+        running.sort_by_key(|build| build.number);
+        running.dedup_by_key(|build| build.number);
+        running
+    }
+
+    /// Announce the builds among `running` whose start has not been announced yet, unless they
+    /// are older than the last completed build. Starts go through the same filters and routing
+    /// rules as completions. Returns the running builds whose start has been announced, now or
+    /// before.
+    fn announce_starts(
+        name: &cache::Name,
+        running: Vec<JBuild>,
+        announced: &[BuildNumber],
+        last_completed: Option<BuildNumber>,
+        j_config: &JenkinsConfig,
+        events: &mut Vec<Event>,
+    ) -> Vec<BuildNumber> {
+        if !j_config.announces_start(&name.0) || !j_config.accepts_job(&name.0) {
+            return Vec::new();
+        }
+        let notify = j_config.start_destinations(&name.0);
+        running
             .into_iter()
-            .for_each(|job| match job.last_build.result {
-                None => {
-                    info!(
-                        "Job {} has a new build, but it is not complete yet",
-                        job.name
-                    );
+            .filter_map(|build| {
+                if announced.contains(&build.number) {
+                    return Some(build.number);
                 }
-                Some(result) => {
-                    let new_timestamp = job.last_build.timestamp;
-                    match self
-                        .most_recent
-                        .insert(&j_config.server, &job.name, &new_timestamp)
-                    {
-                        Some(old_timestamp) => match old_timestamp.cmp(&new_timestamp) {
-                            Ordering::Less => {
-                                info!("Job {} has a new build", job.name);
-                                events.push(Event::UpdatedJob(
-                                    j_config.id.clone(),
-                                    job.name.clone(),
-                                    result.clone(),
-                                    job.last_build.number,
-                                    job.last_build.duration,
-                                    job.last_build.url.clone(),
-                                    j_config.notify.clone(),
-                                ));
-                            }
-                            Ordering::Equal => info!("Job {} was not updated", job.name),
-                            Ordering::Greater => warn!(
-                                "Job {} went back in time from timestamp {} to {}",
-                                job.name, old_timestamp, new_timestamp
-                            ),
-                        },
-                        None => {
-                            info!("Job {} has a new build", job.name);
-                            events.push(Event::UpdatedJob(
-                                j_config.id.clone(),
-                                job.name.clone(),
-                                result.clone(),
-                                job.last_build.number,
-                                job.last_build.duration,
-                                job.last_build.url.clone(),
-                                j_config.notify.clone(),
-                            ));
-                        }
+                if last_completed.is_some_and(|number| build.number < number) || notify.is_empty() {
+                    return None;
+                }
+                info!("Job {} has started build #{}", name, build.number);
+                events.push(Event::StartedJob(StartEvent {
+                    server: j_config.id.clone(),
+                    name: name.clone(),
+                    number: build.number,
+                    url: build.url,
+                    notify: notify.clone(),
+                    causes: Vec::new(),
+                    estimated_duration: None,
+                }));
+                Some(build.number)
+            })
+            .collect()
+    }
+
+    fn update_builds(
+        &mut self,
+        job_vec: Vec<JJob>,
+        j_config: &JenkinsConfig,
+        max_builds: usize,
+    ) -> Vec<Event> {
+        let mut events = Vec::new();
+        job_vec.into_iter().for_each(|job| {
+            let name = job.name.clone();
+            let previous = self
+                .most_recent
+                .lock()
+                .unwrap()
+                .get(&j_config.id, &name)
+                .cloned();
+            let mut previous_result = previous.as_ref().and_then(|entry| entry.outcome().cloned());
+            let announced = previous
+                .as_ref()
+                .map(|entry| entry.started.clone())
+                .unwrap_or_default();
+            let running = Self::running_builds(&job);
+            let (builds, latest) = Self::completed_builds_since(job, previous.as_ref());
+            // the events of the first poll are not sent, so no start is announced
+            let started = if self.warming_up {
+                Vec::new()
+            } else {
+                Self::announce_starts(
+                    &name,
+                    running,
+                    &announced,
+                    latest.as_ref().map(|entry| entry.last_completed),
+                    j_config,
+                    &mut events,
+                )
+            };
+            // a job whose first build is running is recorded as if build #0 had completed
+            let entry = latest
+                .or(previous)
+                .or_else(|| (!started.is_empty()).then(cache::Entry::default));
+            if let Some(mut entry) = entry {
+                entry.started = started;
+                self.most_recent
+                    .lock()
+                    .unwrap()
+                    .insert(&j_config.id, &name, &entry);
+            }
+            if builds.is_empty() {
+                info!("Job {} was not updated", name);
+                return;
+            }
+            let mut builds: Vec<(JBuild, BuildResult, Transition)> = builds
+                .into_iter()
+                .filter_map(|build| {
+                    let result = build.result.clone()?;
+                    let transition = Transition::between(previous_result.as_ref(), &result);
+                    if !result.is_interrupted() {
+                        previous_result = Some(result.clone());
                     }
+                    Some((build, result, transition))
+                })
+                .collect();
+            if builds.len() > max_builds {
+                warn!(
+                    "Job {} has {} new builds, only reporting the last {}",
+                    name,
+                    builds.len(),
+                    max_builds
+                );
+                builds.drain(..builds.len() - max_builds);
+            }
+            builds.into_iter().for_each(|(build, result, transition)| {
+                info!(
+                    "Job {} has a new build #{} ({})",
+                    name, build.number, transition
+                );
+                let notify = j_config.destinations(&name.0, &result, transition);
+                // the completion of builds whose start was announced is always reported
+                let started = announced.contains(&build.number).then_some(build.timestamp);
+                if !(started.is_some() || j_config.notify_on.accepts(transition))
+                    || !j_config.accepts(&name.0, &result)
+                    || notify.is_empty()
+                {
+                    info!(
+                        "Not notifying about build #{} of job {}",
+                        build.number, name
+                    );
+                    return;
                 }
+                events.push(Event::UpdatedJob(BuildEvent {
+                    server: j_config.id.clone(),
+                    name: name.clone(),
+                    number: build.number,
+                    result,
+                    transition,
+                    duration: build.duration,
+                    url: build.url,
+                    notify,
+                    details: BuildDetails::default(),
+                    started,
+                }));
             });
+        });
         events
     }
 
-    fn update(&mut self, job_vec: Vec<JJob>, j_config: &JenkinsConfig) -> Vec<Event> {
+    fn update(
+        &mut self,
+        job_vec: JJobVec,
+        j_config: &JenkinsConfig,
+        max_builds: usize,
+    ) -> Vec<Event> {
         self.prune_missing_builds(&job_vec, j_config);
-        info!("Updating with jobs: {:?}", job_vec);
-        self.update_builds(job_vec, j_config)
+        info!("Updating with jobs: {:?}", job_vec.0);
+        self.update_builds(job_vec.0, j_config, max_builds)
     }
 
-    pub fn listen(&mut self, config: Config) {
+    /// Poll the Jenkins servers forever. Events received on `refresh` trigger an immediate
+    /// poll, after which they are sent back, so that they are handled with fresh data.
+    pub fn listen(&mut self, config: Arc<Config>, refresh: Receiver<Event>) {
         let client = Client::new();
-        // update once at the beginning without sending any messages
-        self.update_cache(&client, &config);
+        if self.load_state(&config) {
+            // report whatever happened since the state was saved
+            self.poll(&client, &config);
+        } else {
+            // update once at the beginning without sending any messages
+            self.warming_up = true;
+            self.update_cache(&client, &config);
+            self.warming_up = false;
+            self.save_state(&config);
+        }
         loop {
-            sleep(Duration::from_secs(config.sleep));
-            self.update_cache(&client, &config)
+            match refresh.recv_timeout(Duration::from_secs(config.sleep)) {
+                Ok(event) => {
+                    self.poll(&client, &config);
+                    // the requests received during the poll are answered without polling again
+                    iter::once(event)
+                        .chain(refresh.try_iter())
+                        .for_each(|event| self.tx.send(event).unwrap());
+                }
+                Err(RecvTimeoutError::Timeout) => self.poll(&client, &config),
+                Err(RecvTimeoutError::Disconnected) => {
+                    sleep(Duration::from_secs(config.sleep));
+                    self.poll(&client, &config);
+                }
+            }
+        }
+    }
+
+    fn poll(&mut self, client: &Client, config: &Config) {
+        self.console_excerpts = 0;
+        self.update_cache(client, config)
+            .into_iter()
+            .for_each(|mut event| {
+                match event {
+                    Event::UpdatedJob(ref mut build) => self.add_details(client, config, build),
+                    Event::StartedJob(ref mut start) => {
+                        if let Some(j_config) = config.jenkins(&start.server) {
+                            details::fetch_start(client, j_config, start);
+                        }
+                    }
+                    _ => (),
+                }
+                info!("Sending event: {:?}", event);
+                self.tx.send(event).unwrap();
+            });
+        self.save_state(config);
+        self.check_queues(client, config);
+    }
+
+    /// Alert about the items waiting too long in the queues of the servers that have a queue
+    /// monitor. The alerts go through the same filters and routing rules as build starts.
+    fn check_queues(&mut self, client: &Client, config: &Config) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_millis() as u64)
+            .unwrap_or(0);
+        config.job.iter().for_each(|j_config| {
+            let queue = match j_config.queue {
+                Some(ref queue) => queue,
+                None => return,
+            };
+            self.queue_monitor
+                .poll(client, j_config, queue, now)
                 .into_iter()
-                .for_each(|event| {
-                    info!("Sending event: {:?}", event);
-                    self.tx.send(event).unwrap();
+                .for_each(|(name, message)| {
+                    j_config.queue_destinations(&name).iter().for_each(|dest| {
+                        let event = Event::Reply(dest.clone(), message.clone());
+                        info!("Sending event: {:?}", event);
+                        self.tx.send(event).unwrap();
+                    })
                 });
+        });
+    }
+
+    /// Fetch the information about `build` that is not part of the job list.
+    fn add_details(&mut self, client: &Client, config: &Config, build: &mut BuildEvent) {
+        let j_config = match config.jenkins(&build.server) {
+            Some(j_config) => j_config,
+            None => return,
+        };
+        details::fetch(client, config, j_config, build);
+        if build.result.is_success() {
+            return;
+        }
+        if let Some(ref console) = j_config.console {
+            if console.accepts(&build.name.0)
+                && self.may_post_console(build, console.min_interval, config.max_console_excerpts)
+            {
+                build.details.console = console::fetch(client, j_config, console, build);
+            }
+        }
+    }
+
+    /// Whether the console output of `build` may be posted, given that excerpts for the same
+    /// job must be at least `min_interval` seconds apart, and that at most `max_excerpts` are
+    /// posted per poll. Records the post if it may.
+    fn may_post_console(
+        &mut self,
+        build: &BuildEvent,
+        min_interval: u64,
+        max_excerpts: usize,
+    ) -> bool {
+        if self.console_excerpts >= max_excerpts {
+            info!(
+                "Not posting console output of build #{} of job {}: {} excerpts already posted",
+                build.number, build.name, max_excerpts
+            );
+            return false;
+        }
+        let key = (build.server.clone(), build.name.clone());
+        let now = Instant::now();
+        match self.console_posted.get(&key) {
+            Some(last) if now.duration_since(*last) < Duration::from_secs(min_interval) => {
+                info!(
+                    "Not posting console output of build #{} of job {}: rate limited",
+                    build.number, build.name
+                );
+                false
+            }
+            _ => {
+                self.console_posted.insert(key, now);
+                self.console_excerpts += 1;
+                true
+            }
+        }
+    }
+
+    /// Load the cache from the state file, if any. Returns true if a saved state was found.
+    fn load_state(&mut self, config: &Config) -> bool {
+        let path = match config.state_file {
+            Some(ref path) => path,
+            None => return false,
+        };
+        match cache::Cache::load(path) {
+            Ok(Some(mut cache)) => {
+                info!("Loaded state from {}", path);
+                let servers: Vec<(&str, &String)> = config
+                    .job
+                    .iter()
+                    .map(|j_config| (j_config.server.as_str(), &j_config.id))
+                    .collect();
+                cache.migrate(&servers);
+                *self.most_recent.lock().unwrap() = cache;
+                true
+            }
+            Ok(None) => {
+                info!("State file {} does not exist yet", path);
+                false
+            }
+            Err(err) => {
+                error!("Could not load state from {}: {}", path, err);
+                false
+            }
+        }
+    }
+
+    fn save_state(&self, config: &Config) {
+        if let Some(ref path) = config.state_file {
+            if let Err(err) = self.most_recent.lock().unwrap().save(path) {
+                error!("Could not save state to {}: {}", path, err);
+            }
         }
     }
 
@@ -182,16 +866,26 @@ impl JListener {
         config
             .job
             .iter()
-            .flat_map(|j_config| match self.attempt(client, &j_config) {
-                Ok(json) => {
-                    let job_vec = json.jobs.0;
-                    self.update(job_vec, &j_config)
-                }
-                Err(err) => {
-                    error!("Request to {} failed with message {}", j_config.id, err);
-                    Vec::new()
-                }
-            }).collect()
+            .flat_map(|j_config| self.update_server(client, config, j_config))
+            .collect()
+    }
+
+    fn update_server(
+        &mut self,
+        client: &Client,
+        config: &Config,
+        j_config: &JenkinsConfig,
+    ) -> Vec<Event> {
+        match self.attempt(client, j_config, config.max_builds) {
+            Ok(json) => {
+                let job_vec = json.into_jobs(j_config.depth);
+                self.update(job_vec, j_config, config.max_builds)
+            }
+            Err(err) => {
+                error!("Request to {} failed with message {}", j_config.id, err);
+                Vec::new()
+            }
+        }
     }
 }
 
@@ -199,6 +893,7 @@ impl JListener {
 mod tests {
     use super::cache::tests::{caches, names, timestamps};
     use super::*;
+    use crate::config::{NotifyPolicy, Pattern};
     use proptest::prelude::*;
     use std::sync::mpsc::{channel, Receiver};
 
@@ -221,7 +916,20 @@ mod tests {
     }
 
     prop_compose! {
-        [pub] fn j_builds()(result in any::<Option<String>>(),
+        [pub] fn build_results()(result in prop_oneof![
+            Just(String::from("SUCCESS")),
+            Just(String::from("UNSTABLE")),
+            Just(String::from("FAILURE")),
+            Just(String::from("ABORTED")),
+            Just(String::from("NOT_BUILT")),
+            any::<String>(),
+        ]) -> BuildResult {
+            BuildResult::from(result)
+        }
+    }
+
+    prop_compose! {
+        [pub] fn j_builds()(result in prop::option::of(build_results()),
                      timestamp in timestamps(),
                      number in build_numbers(),
                      duration in build_durations(),
@@ -233,27 +941,547 @@ mod tests {
 
     prop_compose! {
         [pub] fn j_jobs()(name in names(),
-                   last_build in j_builds()) -> JJob {
-            JJob { name, last_build }
+                   last_build in j_builds(),
+                   last_completed_build in prop::option::of(j_builds()),
+                   builds in prop::collection::vec(j_builds(), 0..5)) -> JJob {
+            JJob { name, last_build, last_completed_build, builds }
         }
     }
 
     prop_compose! {
         fn j_job_vecs()(job_vec in prop::collection::vec(j_jobs(), 1..50)) -> JJobVec {
-            JJobVec(job_vec)
+            JJobVec(job_vec, Vec::new())
         }
     }
 
     prop_compose! {
-        fn j_jsons()(jobs in j_job_vecs()) -> JJson {
-            JJson{ jobs }
+        fn j_items()(job in j_jobs()) -> JItem {
+            JItem {
+                name: job.name.0,
+                last_build: Some(Some(job.last_build)),
+                last_completed_build: job.last_completed_build,
+                builds: job.builds,
+                ..JItem::default()
+            }
+        }
+    }
+
+    prop_compose! {
+        fn j_folders()(name in any::<String>(),
+                       jobs in prop::collection::vec(j_items(), 0..5)) -> JItem {
+            JItem {
+                name,
+                jobs: Some(jobs),
+                ..JItem::default()
+            }
+        }
+    }
+
+    prop_compose! {
+        fn j_jsons()(jobs in prop::collection::vec(prop_oneof![j_items(), j_folders()], 1..10))
+                     -> JJson {
+            JJson { jobs: Some(jobs) }
         }
     }
 
     prop_compose! {
         [pub] fn j_listeners()(most_recent in caches(1, 5, 1, 10)) -> (JListener, Receiver<Event>) {
             let (tx, rx) = channel();
-            (JListener { tx, most_recent }, rx)
+            (JListener::new(tx, Arc::new(Mutex::new(most_recent))), rx)
+        }
+    }
+
+    fn a_build(number: u32, result: Option<&str>) -> JBuild {
+        JBuild {
+            result: result.map(BuildResult::from),
+            timestamp: cache::Timestamp(1000 * u64::from(number)),
+            number: BuildNumber(number),
+            duration: BuildDuration(1000),
+            url: BuildUrl(format!("http://jenkins/job/a/{}/", number)),
+        }
+    }
+
+    fn a_job(builds: Vec<JBuild>) -> JJob {
+        JJob {
+            name: cache::Name(String::from("a")),
+            last_build: builds[0].clone(),
+            last_completed_build: None,
+            builds,
+        }
+    }
+
+    const CONFIG: &str = r##"
+sleep = 60
+
+[[job]]
+server = "http://jenkins/api/json"
+id = "jenkins"
+user = "carlo"
+token = "secret"
+notify = ["#chan"]
+"##;
+
+    /// The server of `CONFIG`, with the settings in `extra` added to it.
+    fn a_config_with(extra: &str) -> JenkinsConfig {
+        let config = format!("{}{}", CONFIG, extra);
+        Config::from_string(&config).unwrap().job.remove(0)
+    }
+
+    fn a_config() -> JenkinsConfig {
+        a_config_with("")
+    }
+
+    fn reported_numbers(events: &[Event]) -> Vec<u32> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Event::UpdatedJob(build) => Some(build.number.0),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn a_listener() -> JListener {
+        let (tx, _rx) = channel();
+        JListener::new(tx, Arc::new(Mutex::new(cache::Cache::new())))
+    }
+
+    #[test]
+    fn tracks_broken_since() {
+        let mut listener = a_listener();
+        let config = a_config();
+        let mut poll = |results: &[(u32, &str)]| {
+            let builds = results
+                .iter()
+                .map(|(number, result)| a_build(*number, Some(result)))
+                .collect();
+            listener.update_builds(vec![a_job(builds)], &config, 5);
+            let cache = listener.most_recent.lock().unwrap();
+            let name = cache::Name(String::from("a"));
+            let since = cache.get(&config.id, &name).unwrap().broken_since;
+            since.map(|since| since.0 / 1000)
+        };
+        // failing since before the oldest known build
+        assert_eq!(poll(&[(3, "FAILURE"), (2, "FAILURE")]), None);
+        assert_eq!(
+            poll(&[(5, "FAILURE"), (4, "FAILURE"), (3, "FAILURE")]),
+            None
+        );
+
+        assert_eq!(
+            poll(&[(7, "FAILURE"), (6, "SUCCESS"), (5, "FAILURE")]),
+            Some(7)
+        );
+        // the successful build #6 is older than the last known build
+        assert_eq!(
+            poll(&[(8, "UNSTABLE"), (7, "FAILURE"), (6, "SUCCESS")]),
+            Some(7)
+        );
+        assert_eq!(poll(&[(9, "SUCCESS"), (8, "UNSTABLE")]), None);
+        assert_eq!(
+            poll(&[
+                (12, "FAILURE"),
+                (11, "SUCCESS"),
+                (10, "FAILURE"),
+                (9, "SUCCESS")
+            ]),
+            Some(12)
+        );
+        // interrupted builds neither break nor fix the job
+        assert_eq!(poll(&[(13, "ABORTED"), (12, "FAILURE")]), Some(12));
+        assert_eq!(poll(&[(14, "SUCCESS"), (13, "ABORTED")]), None);
+        assert_eq!(poll(&[(15, "FAILURE"), (14, "SUCCESS")]), Some(15));
+
+        let mut listener = a_listener();
+        let builds = vec![a_build(2, Some("FAILURE")), a_build(1, Some("FAILURE"))];
+        listener.update_builds(vec![a_job(builds)], &config, 5);
+        let cache = listener.most_recent.lock().unwrap();
+        let entry = cache.get(&config.id, &cache::Name(String::from("a")));
+        assert_eq!(entry.unwrap().broken_since, Some(cache::Timestamp(1000)));
+    }
+
+    #[test]
+    fn rate_limits_console_excerpts() {
+        let mut listener = a_listener();
+        let build = |name: &str| BuildEvent {
+            name: cache::Name(name.to_string()),
+            ..event::tests::build_event()
+        };
+        assert!(listener.may_post_console(&build("a"), 300, 2));
+        assert!(!listener.may_post_console(&build("a"), 300, 2));
+        assert!(listener.may_post_console(&build("b"), 300, 2));
+        assert!(!listener.may_post_console(&build("c"), 300, 2));
+        listener.console_excerpts = 0;
+        assert!(listener.may_post_console(&build("c"), 300, 2));
+    }
+
+    #[test]
+    fn reports_all_builds_since_last_seen() {
+        let mut listener = a_listener();
+        let config = a_config();
+        let job = a_job(vec![a_build(2, Some("SUCCESS"))]);
+        assert_eq!(
+            reported_numbers(&listener.update_builds(vec![job], &config, 5)),
+            vec![2]
+        );
+
+        let builds = vec![
+            a_build(6, None),
+            a_build(5, Some("FAILURE")),
+            a_build(4, Some("SUCCESS")),
+            a_build(3, Some("SUCCESS")),
+            a_build(2, Some("SUCCESS")),
+        ];
+        let events = listener.update_builds(vec![a_job(builds)], &config, 5);
+        assert_eq!(reported_numbers(&events), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn caps_reported_builds() {
+        let mut listener = a_listener();
+        let config = a_config();
+        let job = a_job(vec![a_build(1, Some("SUCCESS"))]);
+        listener.update_builds(vec![job], &config, 5);
+
+        let builds = (2..10).rev().map(|n| a_build(n, Some("SUCCESS"))).collect();
+        let events = listener.update_builds(vec![a_job(builds)], &config, 2);
+        assert_eq!(reported_numbers(&events), vec![8, 9]);
+    }
+
+    #[test]
+    fn reports_last_completed_build_while_running() {
+        let mut listener = a_listener();
+        let config = a_config();
+        let job = a_job(vec![a_build(1, Some("SUCCESS"))]);
+        listener.update_builds(vec![job], &config, 5);
+
+        let job = JJob {
+            name: cache::Name(String::from("a")),
+            last_build: a_build(3, None),
+            last_completed_build: Some(a_build(2, Some("FAILURE"))),
+            builds: Vec::new(),
+        };
+        let events = listener.update_builds(vec![job], &config, 5);
+        assert_eq!(reported_numbers(&events), vec![2]);
+    }
+
+    #[test]
+    fn reports_builds_completed_out_of_order() {
+        let mut listener = a_listener();
+        let config = a_config();
+        let job = a_job(vec![a_build(1, Some("SUCCESS"))]);
+        listener.update_builds(vec![job], &config, 5);
+
+        let builds = vec![
+            a_build(3, Some("SUCCESS")),
+            a_build(2, None),
+            a_build(1, Some("SUCCESS")),
+        ];
+        let events = listener.update_builds(vec![a_job(builds)], &config, 5);
+        assert_eq!(reported_numbers(&events), vec![3]);
+
+        let builds = vec![
+            a_build(3, Some("SUCCESS")),
+            a_build(2, Some("FAILURE")),
+            a_build(1, Some("SUCCESS")),
+        ];
+        let events = listener.update_builds(vec![a_job(builds)], &config, 5);
+        assert_eq!(reported_numbers(&events), vec![2]);
+    }
+
+    #[test]
+    fn classifies_transitions() {
+        assert_eq!(
+            Transition::between(None, &BuildResult::Success),
+            Transition::StillGreen
+        );
+        assert_eq!(
+            Transition::between(None, &BuildResult::Failure),
+            Transition::Broken
+        );
+        assert_eq!(
+            Transition::between(Some(&BuildResult::Success), &BuildResult::Success),
+            Transition::StillGreen
+        );
+        assert_eq!(
+            Transition::between(Some(&BuildResult::Success), &BuildResult::Unstable),
+            Transition::Broken
+        );
+        assert_eq!(
+            Transition::between(Some(&BuildResult::Failure), &BuildResult::Failure),
+            Transition::StillFailing
+        );
+        assert_eq!(
+            Transition::between(Some(&BuildResult::Failure), &BuildResult::Success),
+            Transition::Fixed
+        );
+        assert_eq!(
+            Transition::between(Some(&BuildResult::Success), &BuildResult::Aborted),
+            Transition::Interrupted
+        );
+        assert_eq!(
+            Transition::between(Some(&BuildResult::Failure), &BuildResult::NotBuilt),
+            Transition::Interrupted
+        );
+    }
+
+    #[test]
+    fn only_notifies_on_change() {
+        let mut listener = a_listener();
+        let mut config = a_config();
+        config.notify_on = NotifyPolicy::Change;
+        let job = a_job(vec![a_build(1, Some("SUCCESS"))]);
+        listener.update_builds(vec![job], &config, 5);
+
+        let builds = vec![
+            a_build(5, Some("SUCCESS")),
+            a_build(4, Some("FAILURE")),
+            a_build(3, Some("FAILURE")),
+            a_build(2, Some("SUCCESS")),
+            a_build(1, Some("SUCCESS")),
+        ];
+        let events = listener.update_builds(vec![a_job(builds)], &config, 5);
+        assert_eq!(reported_numbers(&events), vec![3, 5]);
+    }
+
+    #[test]
+    fn ignores_interrupted_builds() {
+        let mut listener = a_listener();
+        let mut config = a_config();
+        config.notify_on = NotifyPolicy::Change;
+        let job = a_job(vec![a_build(1, Some("SUCCESS"))]);
+        listener.update_builds(vec![job], &config, 5);
+
+        let builds = vec![a_build(2, Some("ABORTED")), a_build(1, Some("SUCCESS"))];
+        assert!(listener
+            .update_builds(vec![a_job(builds)], &config, 5)
+            .is_empty());
+        let builds = vec![
+            a_build(4, Some("FAILURE")),
+            a_build(3, Some("SUCCESS")),
+            a_build(2, Some("ABORTED")),
+        ];
+        let events = listener.update_builds(vec![a_job(builds)], &config, 5);
+        assert_eq!(reported_numbers(&events), vec![4]);
+
+        let builds = vec![a_build(6, Some("SUCCESS")), a_build(5, Some("NOT_BUILT"))];
+        let events = listener.update_builds(vec![a_job(builds)], &config, 5);
+        assert_eq!(reported_numbers(&events), vec![6]);
+        let cache = listener.most_recent.lock().unwrap();
+        let entry = cache.get(&config.id, &cache::Name(String::from("a")));
+        assert_eq!(entry.unwrap().outcome(), Some(&BuildResult::Success));
+    }
+
+    #[test]
+    fn filters_jobs() {
+        let mut listener = a_listener();
+        let mut config = a_config();
+        config.exclude = vec![Pattern::parse("a").unwrap()];
+        let job = a_job(vec![a_build(1, Some("FAILURE"))]);
+        assert!(listener.update_builds(vec![job], &config, 5).is_empty());
+        config.exclude.clear();
+        config.results = vec![BuildResult::Failure];
+        let builds = vec![a_build(3, Some("SUCCESS")), a_build(2, Some("FAILURE"))];
+        let events = listener.update_builds(vec![a_job(builds)], &config, 5);
+        assert_eq!(reported_numbers(&events), vec![2]);
+    }
+
+    #[test]
+    fn announces_starts_once() {
+        let mut listener = a_listener();
+        let mut config = a_config();
+        config.notify_on = NotifyPolicy::Change;
+        config.notify_on_start = vec![Pattern::parse("a").unwrap()];
+        let started_numbers = |events: &[Event]| -> Vec<u32> {
+            events
+                .iter()
+                .filter_map(|event| match event {
+                    Event::StartedJob(start) => Some(start.number.0),
+                    _ => None,
+                })
+                .collect()
+        };
+        let job = a_job(vec![a_build(1, None)]);
+        let events = listener.update_builds(vec![job], &config, 5);
+        assert_eq!(started_numbers(&events), vec![1]);
+
+        let builds = vec![a_build(2, None), a_build(1, None)];
+        let events = listener.update_builds(vec![a_job(builds)], &config, 5);
+        assert_eq!(started_numbers(&events), vec![2]);
+
+        // still green, but reported because the start was announced
+        let builds = vec![a_build(2, Some("SUCCESS")), a_build(1, Some("SUCCESS"))];
+        let events = listener.update_builds(vec![a_job(builds)], &config, 5);
+        assert!(started_numbers(&events).is_empty());
+        assert_eq!(reported_numbers(&events), vec![1, 2]);
+        assert!(events.iter().all(|event| match event {
+            Event::UpdatedJob(build) => build.started.is_some(),
+            _ => false,
+        }));
+
+        let builds = vec![a_build(3, Some("SUCCESS")), a_build(2, Some("SUCCESS"))];
+        let events = listener.update_builds(vec![a_job(builds)], &config, 5);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn filters_and_routes_starts() {
+        let mut listener = a_listener();
+        let mut config = a_config();
+        config.notify_on_start = vec![Pattern::parse("*").unwrap()];
+        config.exclude = vec![Pattern::parse("a").unwrap()];
+        let job = a_job(vec![a_build(1, None)]);
+        assert!(listener.update_builds(vec![job], &config, 5).is_empty());
+
+        config.exclude.clear();
+        config.route = a_config_with(
+            r##"
+[[job.route]]
+jobs = ["a"]
+notify = ["#release"]
+"##,
+        )
+        .route;
+        let job = a_job(vec![a_build(1, None)]);
+        let events = listener.update_builds(vec![job], &config, 5);
+        match events[..] {
+            [Event::StartedJob(ref start)] => assert_eq!(start.notify, vec!["#release"]),
+            _ => panic!("unexpected events {:?}", events),
+        }
+    }
+
+    #[test]
+    fn does_not_record_starts_while_warming_up() {
+        let mut listener = a_listener();
+        let mut config = a_config();
+        config.notify_on_start = vec![Pattern::parse("a").unwrap()];
+        listener.warming_up = true;
+        let builds = vec![a_build(2, None), a_build(1, Some("SUCCESS"))];
+        listener.update_builds(vec![a_job(builds)], &config, 5);
+        listener.warming_up = false;
+        let cache = listener.most_recent.lock().unwrap();
+        let entry = cache.get(&config.id, &cache::Name(String::from("a")));
+        assert!(entry.unwrap().started.is_empty());
+    }
+
+    #[test]
+    fn builds_tree_query() {
+        let item = "_class,name,buildable,color,\
+                    lastBuild[number,result,timestamp,duration,url],\
+                    lastCompletedBuild[number,result,timestamp,duration,url],\
+                    builds[number,result,timestamp,duration,url]{0,7}";
+        assert_eq!(jobs_tree(0, 7), format!("jobs[{}]", item));
+        assert_eq!(
+            jobs_tree(2, 7),
+            format!("jobs[{0},jobs[{0},jobs[{0}]]]", item)
+        );
+    }
+
+    #[test]
+    fn collects_jobs_in_folders() {
+        let json = r#"{"jobs": [
+            {"name": "top", "lastBuild": {"result": "SUCCESS", "timestamp": 1, "number": 1,
+                                          "duration": 1, "url": "http://jenkins/job/top/1/"}},
+            {"name": "team", "jobs": [
+                {"name": "never-built", "lastBuild": null},
+                {"name": "project", "jobs": [
+                    {"name": "main", "lastBuild": {"result": null, "timestamp": 1, "number": 3,
+                                                   "duration": 0, "url": "http://jenkins/x/3/"}}
+                ]}
+            ]}
+        ]}"#;
+        let names = |depth| -> Vec<String> {
+            let json: JJson = serde_json::from_str(json).unwrap();
+            json.into_jobs(depth)
+                .0
+                .into_iter()
+                .map(|job| job.name.0)
+                .collect()
+        };
+        assert_eq!(names(5), vec!["top", "team/project/main"]);
+        assert_eq!(names(1), vec!["top"]);
+    }
+
+    #[test]
+    fn skips_unexpected_items() {
+        let json = r#"{"jobs": [
+            {"name": "ok", "lastBuild": {"result": "SUCCESS", "timestamp": 1, "number": 1,
+                                         "duration": 1, "url": "http://jenkins/job/ok/1/"}},
+            {"name": "disabled", "color": "disabled",
+             "lastBuild": {"result": "SUCCESS", "timestamp": 1, "number": 1,
+                           "duration": 1, "url": "http://jenkins/job/disabled/1/"}},
+            {"name": "broken", "lastBuild": {"number": "one"}},
+            {"_class": "hudson.model.ExternalJob"},
+            {"name": "view", "_class": "hudson.model.ListView"},
+            {"name": "never-built", "lastBuild": null}
+        ]}"#;
+        let json: JJson = serde_json::from_str(json).unwrap();
+        let job_vec = json.into_jobs(5);
+        let names: Vec<&str> = job_vec.0.iter().map(|job| job.name.0.as_str()).collect();
+        assert_eq!(names, vec!["ok"]);
+        let skipped: Vec<&str> = job_vec.1.iter().map(|name| name.0.as_str()).collect();
+        assert_eq!(skipped, vec!["disabled", "broken", "view", "never-built"]);
+    }
+
+    #[test]
+    fn keeps_skipped_jobs() {
+        let mut listener = a_listener();
+        let config = a_config();
+        let job = |name: &str| JJob {
+            name: cache::Name(name.to_string()),
+            ..a_job(vec![a_build(1, Some("SUCCESS"))])
+        };
+        let jobs = vec![job("a"), job("b"), job("folder/c"), job("d")];
+        listener.update(JJobVec(jobs, Vec::new()), &config, 5);
+
+        let skipped = vec![
+            cache::Name(String::from("b")),
+            cache::Name(String::from("folder")),
+        ];
+        listener.update(JJobVec(vec![job("a")], skipped), &config, 5);
+        let cache = listener.most_recent.lock().unwrap();
+        let mut names: Vec<&str> = cache
+            .entries()
+            .map(|(_, name, _)| name.0.as_str())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["a", "b", "folder/c"]);
+    }
+
+    proptest! {
+        #[test]
+        fn collects_all_built_jobs(json in j_jsons()) {
+            let n_built: usize = json
+                .jobs
+                .iter()
+                .flatten()
+                .map(|item| item.jobs.as_ref().map_or(1, Vec::len))
+                .sum();
+            assert_eq!(json.into_jobs(5).0.len(), n_built);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn build_results_round_trip(result in build_results()) {
+            let json = serde_json::to_string(&result).unwrap();
+            assert_eq!(serde_json::from_str::<BuildResult>(&json).unwrap(), result);
+        }
+    }
+
+    proptest! {
+        // every case polls up to 200 jobs
+        #![proptest_config(ProptestConfig::with_cases(32))]
+        #[test]
+        fn never_reports_more_than_max_builds(job_vecs in prop::collection::vec(j_job_vecs(), 1..5),
+                                              max_builds in 1usize..5) {
+            let mut listener = a_listener();
+            let config = a_config();
+            job_vecs.into_iter().for_each(|job_vec| {
+                let n_jobs = job_vec.0.len();
+                let events = listener.update_builds(job_vec.0, &config, max_builds);
+                assert!(events.len() <= n_jobs * max_builds);
+            });
         }
     }
 }
@@ -0,0 +1,404 @@
+//! Alerts about items waiting too long in the build queue.
+
+use std::collections::HashMap;
+
+use reqwest::blocking::Client;
+use reqwest::Error;
+
+use super::cache::Timestamp;
+use super::BuildNumber;
+use crate::config::{JenkinsConfig, QueueConfig};
+
+#[derive(Deserialize, Debug, Clone)]
+struct JTask {
+    /// Replaced by the path of the job from the watched folder by `fetch`
+    name: String,
+    #[serde(default)]
+    url: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JQueueItem {
+    id: u64,
+    in_queue_since: Timestamp,
+    /// Why the item is waiting
+    why: Option<String>,
+    task: JTask,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct JQueue {
+    #[serde(default)]
+    items: Vec<JQueueItem>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct JViewJob {
+    name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct JView {
+    #[serde(default)]
+    jobs: Vec<JViewJob>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct JExecutable {
+    number: BuildNumber,
+}
+
+/// What became of an item after it left the queue.
+#[derive(Deserialize, Debug, Clone)]
+struct JLeftItem {
+    #[serde(default)]
+    cancelled: bool,
+    executable: Option<JExecutable>,
+}
+
+/// An item that has been reported as waiting too long.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Alert {
+    name: String,
+    since: Timestamp,
+}
+
+/// Watches the queues of the Jenkins servers and remembers the items it alerted about, by
+/// server id and queue item id.
+#[derive(Debug, Default)]
+pub struct QueueMonitor {
+    alerts: HashMap<(String, u64), Alert>,
+}
+
+impl QueueMonitor {
+    pub fn new() -> QueueMonitor {
+        QueueMonitor::default()
+    }
+
+    /// Check the queue of `j_config`, and return the messages to send about items that have
+    /// been waiting for longer than the threshold, or that have left the queue after an alert,
+    /// with the name of their job.
+    pub fn poll(
+        &mut self,
+        client: &Client,
+        j_config: &JenkinsConfig,
+        queue: &QueueConfig,
+        now: u64,
+    ) -> Vec<(String, String)> {
+        let items = match fetch(client, j_config) {
+            Ok(items) => items,
+            Err(err) => {
+                error!("Could not fetch the queue of {}: {}", j_config.id, err);
+                return Vec::new();
+            }
+        };
+        let mut messages = self.check(j_config, queue, &items, now);
+        self.left(j_config, &items)
+            .into_iter()
+            .for_each(|(id, alert)| {
+                let waited = minutes(now, alert.since);
+                let fate = match fetch_left_item(client, j_config, id) {
+                    Ok(JLeftItem {
+                        cancelled: true, ..
+                    }) => String::from("was cancelled"),
+                    Ok(JLeftItem {
+                        executable: Some(build),
+                        ..
+                    }) => format!("has started build #{}", build.number),
+                    Ok(_) => String::from("has left the queue"),
+                    Err(err) => {
+                        warn!(
+                            "Could not fetch queue item {} of {}: {}",
+                            id, j_config.id, err
+                        );
+                        String::from("has left the queue")
+                    }
+                };
+                let message = format!(
+                    "Job '{}' on '{}' {} after waiting {} min",
+                    alert.name, j_config.id, fate, waited
+                );
+                messages.push((alert.name, message));
+            });
+        messages
+    }
+
+    /// Record the items of the jobs passing the filters of `j_config` that have been waiting
+    /// for too long, and return the alerts about the ones that have not been reported yet.
+    fn check(
+        &mut self,
+        j_config: &JenkinsConfig,
+        queue: &QueueConfig,
+        items: &[JQueueItem],
+        now: u64,
+    ) -> Vec<(String, String)> {
+        items
+            .iter()
+            .filter(|item| now.saturating_sub(item.in_queue_since.0) >= queue.threshold * 1000)
+            .filter(|item| j_config.accepts_job(&item.task.name))
+            .filter_map(|item| {
+                let key = (j_config.id.clone(), item.id);
+                if self.alerts.contains_key(&key) {
+                    return None;
+                }
+                let alert = Alert {
+                    name: item.task.name.clone(),
+                    since: item.in_queue_since,
+                };
+                self.alerts.insert(key, alert);
+                let mut message = format!(
+                    "Job '{}' on '{}' has been waiting in the queue for {} min",
+                    item.task.name,
+                    j_config.id,
+                    minutes(now, item.in_queue_since)
+                );
+                if let Some(ref why) = item.why {
+                    message.push_str(&format!(": {}", why));
+                }
+                Some((item.task.name.clone(), message))
+            })
+            .collect()
+    }
+
+    /// Forget the alerted items of `j_config` that are not among `items` anymore, and return
+    /// them by queue item id.
+    fn left(&mut self, j_config: &JenkinsConfig, items: &[JQueueItem]) -> Vec<(u64, Alert)> {
+        let left: Vec<(String, u64)> = self
+            .alerts
+            .keys()
+            .filter(|(server, id)| {
+                *server == j_config.id && !items.iter().any(|item| item.id == *id)
+            })
+            .cloned()
+            .collect();
+        let mut left: Vec<(u64, Alert)> = left
+            .into_iter()
+            .filter_map(|key| self.alerts.remove(&key).map(|alert| (key.1, alert)))
+            .collect();
+        left.sort_by_key(|(id, _)| *id);
+        left
+    }
+}
+
+fn minutes(now: u64, since: Timestamp) -> u64 {
+    now.saturating_sub(since.0) / 60_000
+}
+
+/// The items of the queue of the Jenkins instance of `j_config` that belong to the watched
+/// folder and view, named like the watched jobs.
+fn fetch(client: &Client, j_config: &JenkinsConfig) -> Result<Vec<JQueueItem>, Error> {
+    let view = match j_config.view {
+        Some(_) => Some(view_jobs(client, j_config)?),
+        None => None,
+    };
+    let queue: JQueue = client
+        .get(format!("{}/queue/api/json", j_config.base_url()))
+        .query(&[("tree", "items[id,inQueueSince,why,task[name,url]]")])
+        .basic_auth(&j_config.user, Some(&j_config.token))
+        .send()?
+        .error_for_status()?
+        .json()?;
+    let root = j_config.job_url("") == j_config.base_url();
+    let folder = format!("{}/", j_config.job_url(""));
+    Ok(queue
+        .items
+        .into_iter()
+        .filter_map(|mut item| {
+            match item.task.url.strip_prefix(&folder) {
+                Some(path) => {
+                    if let Some(name) = job_path(path) {
+                        item.task.name = name;
+                    }
+                }
+                // the whole instance is watched, even if it reports another URL
+                None if root => (),
+                None => return None,
+            }
+            Some(item)
+        })
+        .filter(|item| {
+            view.as_ref()
+                .is_none_or(|jobs| in_view(&item.task.name, jobs))
+        })
+        .collect())
+}
+
+/// The names of the jobs and folders listed in the watched view.
+fn view_jobs(client: &Client, j_config: &JenkinsConfig) -> Result<Vec<String>, Error> {
+    let view: JView = client
+        .get(format!("{}/api/json", j_config.view_url()))
+        .query(&[("tree", "jobs[name]")])
+        .basic_auth(&j_config.user, Some(&j_config.token))
+        .send()?
+        .error_for_status()?
+        .json()?;
+    Ok(view.jobs.into_iter().map(|job| job.name).collect())
+}
+
+/// Whether the job at `path` from the watched folder is listed in a view listing `jobs`,
+/// either itself or through one of the folders containing it.
+fn in_view(path: &str, jobs: &[String]) -> bool {
+    let top = path.split('/').next().unwrap_or(path);
+    jobs.iter().any(|job| job == top)
+}
+
+/// The path of a job from the folder of its URL, e.g. `team/main branch` for
+/// `job/team/job/main%20branch/`, or `None` if the URL is not the one of a job.
+fn job_path(url: &str) -> Option<String> {
+    let segments: Vec<&str> = url.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.is_empty() {
+        return None;
+    }
+    let names = segments
+        .chunks(2)
+        .map(|pair| match pair {
+            ["job", name] => Some(decode_path_segment(name)),
+            _ => None,
+        })
+        .collect::<Option<Vec<String>>>()?;
+    Some(names.join("/"))
+}
+
+/// Decode the percent-encoded characters of a URL path segment.
+fn decode_path_segment(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn fetch_left_item(client: &Client, j_config: &JenkinsConfig, id: u64) -> Result<JLeftItem, Error> {
+    client
+        .get(format!(
+            "{}/queue/item/{}/api/json",
+            j_config.base_url(),
+            id
+        ))
+        .query(&[("tree", "cancelled,executable[number]")])
+        .basic_auth(&j_config.user, Some(&j_config.token))
+        .send()?
+        .error_for_status()?
+        .json()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    const MINUTE: u64 = 60_000;
+
+    fn an_item(id: u64, name: &str, since: u64) -> JQueueItem {
+        JQueueItem {
+            id,
+            in_queue_since: Timestamp(since),
+            why: Some(String::from("Waiting for next available executor on linux")),
+            task: JTask {
+                name: name.to_string(),
+                url: format!("http://jenkins/job/{}/", name),
+            },
+        }
+    }
+
+    const CONFIG: &str = r##"
+sleep = 60
+
+[[job]]
+server = "http://jenkins"
+id = "jenkins"
+user = "carlo"
+token = "secret"
+notify = ["#chan"]
+
+[job.queue]
+threshold = 600
+"##;
+
+    #[test]
+    fn alert_once_and_clear() {
+        let config = Config::from_string(CONFIG).unwrap();
+        let j_config = &config.job[0];
+        let queue = j_config.queue.as_ref().unwrap();
+        let mut monitor = QueueMonitor::new();
+        let items = vec![an_item(1, "nightly", 0), an_item(2, "docs", 8 * MINUTE)];
+        assert_eq!(
+            monitor.check(j_config, queue, &items, 12 * MINUTE),
+            vec![(
+                String::from("nightly"),
+                String::from(
+                    "Job 'nightly' on 'jenkins' has been waiting in the queue for 12 min: \
+                     Waiting for next available executor on linux"
+                )
+            )]
+        );
+        assert!(monitor
+            .check(j_config, queue, &items, 13 * MINUTE)
+            .is_empty());
+        assert!(monitor.left(j_config, &items).is_empty());
+
+        let items = vec![an_item(2, "docs", 8 * MINUTE)];
+        assert_eq!(
+            monitor.left(j_config, &items),
+            vec![(
+                1,
+                Alert {
+                    name: String::from("nightly"),
+                    since: Timestamp(0),
+                }
+            )]
+        );
+        assert!(monitor.left(j_config, &items).is_empty());
+        assert_eq!(monitor.check(j_config, queue, &items, 18 * MINUTE).len(), 1);
+    }
+
+    #[test]
+    fn filter_jobs() {
+        let config = CONFIG.replace("[job.queue]", "exclude = [\"docs\"]\n\n[job.queue]");
+        let config = Config::from_string(&config).unwrap();
+        let j_config = &config.job[0];
+        let queue = j_config.queue.as_ref().unwrap();
+        let mut monitor = QueueMonitor::new();
+        let items = vec![an_item(1, "docs", 0), an_item(2, "nightly", 0)];
+        let alerts = monitor.check(j_config, queue, &items, 12 * MINUTE);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].0, "nightly");
+        assert!(monitor.left(j_config, &[]).iter().all(|(id, _)| *id == 2));
+    }
+
+    #[test]
+    fn name_jobs_by_path() {
+        assert_eq!(job_path("job/nightly/"), Some(String::from("nightly")));
+        assert_eq!(
+            job_path("job/team/job/main%20branch/"),
+            Some(String::from("team/main branch"))
+        );
+        assert_eq!(job_path("job/100%25/"), Some(String::from("100%")));
+        assert_eq!(job_path("job/"), None);
+        assert_eq!(job_path("view/all/job/nightly/"), None);
+    }
+
+    #[test]
+    fn filter_by_view() {
+        let jobs = vec![String::from("nightly"), String::from("team")];
+        assert!(in_view("nightly", &jobs));
+        assert!(in_view("team/main branch", &jobs));
+        assert!(!in_view("nightly-docs", &jobs));
+        assert!(!in_view("other/team", &jobs));
+    }
+}
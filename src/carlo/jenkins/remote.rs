@@ -0,0 +1,508 @@
+//! Actions on Jenkins requested from IRC.
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{COOKIE, LOCATION, SET_COOKIE};
+use reqwest::StatusCode;
+
+use super::{BuildDuration, BuildNumber, BuildResult, BuildUrl};
+use crate::config::JenkinsConfig;
+
+/// The header to send to protect a request against CSRF.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JCrumb {
+    crumb: String,
+    crumb_request_field: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct JExecutable {
+    number: BuildNumber,
+    url: BuildUrl,
+}
+
+/// An item of the build queue.
+#[derive(Deserialize, Debug)]
+struct JQueueItem {
+    #[serde(default)]
+    cancelled: bool,
+    /// The build, once it has left the queue
+    executable: Option<JExecutable>,
+}
+
+#[derive(Deserialize, Debug)]
+struct JBuildStatus {
+    result: Option<BuildResult>,
+    duration: BuildDuration,
+}
+
+#[derive(Deserialize, Debug)]
+struct JRunningBuild {
+    number: BuildNumber,
+    result: Option<BuildResult>,
+}
+
+/// Fetch a CSRF crumb, or `None` if CSRF protection is disabled. Since Jenkins 2.176, a
+/// crumb is only valid in the web session it was issued in, so it comes with the cookies of
+/// that session, ready to be sent in a `Cookie` header.
+fn crumb(client: &Client, j_config: &JenkinsConfig) -> Result<Option<(JCrumb, String)>, String> {
+    let url = format!("{}/crumbIssuer/api/json", j_config.base_url());
+    let response = client
+        .get(url)
+        .basic_auth(&j_config.user, Some(&j_config.token))
+        .send()
+        .map_err(|err| err.to_string())?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let response = response.error_for_status().map_err(|err| err.to_string())?;
+    let cookies: Vec<&str> = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|cookie| cookie.to_str().ok())
+        .filter_map(|cookie| cookie.split(';').next())
+        .map(str::trim)
+        .collect();
+    let cookies = cookies.join("; ");
+    response
+        .json()
+        .map(|crumb| Some((crumb, cookies)))
+        .map_err(|err| err.to_string())
+}
+
+/// POST to `url` with the given query parameters, with a CSRF crumb if needed.
+fn post(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    url: &str,
+    query: &[(String, String)],
+) -> Result<Response, String> {
+    let mut request = client
+        .post(url)
+        .query(query)
+        .basic_auth(&j_config.user, Some(&j_config.token));
+    if let Some((crumb, cookies)) = crumb(client, j_config)? {
+        request = request.header(crumb.crumb_request_field.as_str(), crumb.crumb);
+        if !cookies.is_empty() {
+            request = request.header(COOKIE, cookies);
+        }
+    }
+    request
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| err.to_string())
+}
+
+fn get<T>(client: &Client, j_config: &JenkinsConfig, url: &str, tree: &str) -> Result<T, String>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let url = format!("{}/api/json", url.trim_end_matches('/'));
+    client
+        .get(url)
+        .query(&[("tree", tree)])
+        .basic_auth(&j_config.user, Some(&j_config.token))
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.json())
+        .map_err(|err| err.to_string())
+}
+
+/// Queue a build of `job`, and return the URL of the queue item.
+fn queue(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    job: &str,
+    parameters: &[(String, String)],
+) -> Result<String, String> {
+    let endpoint = if parameters.is_empty() {
+        "build"
+    } else {
+        "buildWithParameters"
+    };
+    let url = format!("{}/{}", j_config.job_url(job), endpoint);
+    let response = post(client, j_config, &url, parameters)?;
+    response
+        .headers()
+        .get(LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .map(String::from)
+        .ok_or_else(|| String::from("Jenkins did not return a queue item"))
+}
+
+/// Wait until the queue item at `url` has become a build. Returns `None` if it is still
+/// queued at `deadline`.
+fn wait_for_build(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    url: &str,
+    poll: Duration,
+    deadline: Instant,
+) -> Result<Option<JExecutable>, String> {
+    loop {
+        let item: JQueueItem = get(client, j_config, url, "cancelled,executable[number,url]")?;
+        if item.cancelled {
+            return Err(String::from("the queue item was cancelled"));
+        }
+        if item.executable.is_some() || Instant::now() >= deadline {
+            return Ok(item.executable);
+        }
+        sleep(poll);
+    }
+}
+
+/// Wait until the build at `url` has completed. Returns `None` if it is still running at
+/// `deadline`.
+fn wait_for_result(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    url: &BuildUrl,
+    poll: Duration,
+    deadline: Instant,
+) -> Result<Option<(BuildResult, BuildDuration)>, String> {
+    loop {
+        let build: JBuildStatus = get(client, j_config, &url.0, "result,duration")?;
+        if let Some(result) = build.result {
+            return Ok(Some((result, build.duration)));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        sleep(poll);
+    }
+}
+
+/// Abort build `number` of `job`, or its last build if `number` is not given. Returns the
+/// number of the aborted build.
+pub fn abort(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    job: &str,
+    number: Option<BuildNumber>,
+) -> Result<BuildNumber, String> {
+    let build = match number {
+        Some(number) => number.to_string(),
+        None => String::from("lastBuild"),
+    };
+    let url = format!("{}/{}", j_config.job_url(job), build);
+    let build: JRunningBuild = get(client, j_config, &url, "number,result")?;
+    if let Some(result) = build.result {
+        return Err(format!(
+            "build #{} is not running, its result is {}",
+            build.number, result
+        ));
+    }
+    // Stop the build that was checked, even if another one has started since then
+    let url = format!("{}/{}/stop", j_config.job_url(job), build.number);
+    post(client, j_config, &url, &[])?;
+    Ok(build.number)
+}
+
+/// Trigger a build of `job` with the given parameters and follow it until it completes,
+/// checking its progress every `poll`, for at most `timeout`. Every step is described to
+/// `report`.
+pub fn build<F>(
+    client: &Client,
+    j_config: &JenkinsConfig,
+    job: &str,
+    parameters: &[(String, String)],
+    poll: Duration,
+    timeout: Duration,
+    report: F,
+) where
+    F: Fn(String),
+{
+    let deadline = Instant::now() + timeout;
+    let description = format!("job '{}' on '{}'", job, j_config.id);
+    let queue_url = match queue(client, j_config, job, parameters) {
+        Ok(url) => url,
+        Err(err) => return report(format!("could not trigger {}: {}", description, err)),
+    };
+    report(format!("{} queued: {}", description, queue_url));
+    let build = match wait_for_build(client, j_config, &queue_url, poll, deadline) {
+        Ok(Some(build)) => build,
+        Ok(None) => {
+            return report(format!(
+                "{} is still queued after {} s, no longer following it",
+                description,
+                timeout.as_secs()
+            ))
+        }
+        Err(err) => return report(format!("{} did not start: {}", description, err)),
+    };
+    report(format!(
+        "build #{} of {} started: {}",
+        build.number, description, build.url
+    ));
+    match wait_for_result(client, j_config, &build.url, poll, deadline) {
+        Ok(Some((result, duration))) => report(format!(
+            "build #{} of {} completed! Result: {} after {}",
+            build.number, description, result, duration
+        )),
+        Ok(None) => report(format!(
+            "build #{} of {} is still running after {} s, no longer following it",
+            build.number,
+            description,
+            timeout.as_secs()
+        )),
+        Err(err) => report(format!(
+            "lost track of build #{} of {}: {}",
+            build.number, description, err
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+    use crate::config::Config;
+
+    /// Answer one request per connection with each of the responses returned by `responses`
+    /// for the URL of the server, on a local port. Returns that URL, and the request lines and
+    /// headers received, in lowercase.
+    fn serve<F>(responses: F) -> (String, JoinHandle<Vec<String>>)
+    where
+        F: FnOnce(&str) -> Vec<String>,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let responses = responses(&url);
+        let handle = thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request = String::new();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        request.push_str(&line.to_lowercase());
+                    }
+                    stream.write_all(response.as_bytes()).unwrap();
+                    request
+                })
+                .collect()
+        });
+        (url, handle)
+    }
+
+    fn response(status: &str, headers: &[String], body: &str) -> String {
+        let mut response = format!(
+            "HTTP/1.1 {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            status,
+            body.len()
+        );
+        headers
+            .iter()
+            .for_each(|header| response.push_str(&format!("{}\r\n", header)));
+        response.push_str("\r\n");
+        response.push_str(body);
+        response
+    }
+
+    fn json(body: &str) -> String {
+        response("200 OK", &[], body)
+    }
+
+    fn not_found() -> String {
+        response("404 Not Found", &[], "")
+    }
+
+    fn a_config(url: &str) -> Config {
+        let config = format!(
+            r##"
+sleep = 60
+
+[[job]]
+server = "{}"
+id = "ci"
+user = "carlo"
+token = "secret"
+notify = ["#builds"]
+"##,
+            url
+        );
+        Config::from_string(&config).unwrap()
+    }
+
+    /// Run the `build` command against `url`, and return what it reported.
+    fn run_build(url: &str, parameters: &[(String, String)], timeout: Duration) -> Vec<String> {
+        let config = a_config(url);
+        let reports = RefCell::new(Vec::new());
+        let poll = Duration::from_millis(1);
+        build(
+            &Client::new(),
+            &config.job[0],
+            "nightly",
+            parameters,
+            poll,
+            timeout,
+            |text| reports.borrow_mut().push(text),
+        );
+        reports.into_inner()
+    }
+
+    #[test]
+    fn trigger_and_follow_a_build() {
+        let (url, server) = serve(|url| {
+            vec![
+                response(
+                    "200 OK",
+                    &[String::from(
+                        "Set-Cookie: JSESSIONID.1=abc; Path=/; HttpOnly",
+                    )],
+                    r#"{"crumb": "c0ffee", "crumbRequestField": "Jenkins-Crumb"}"#,
+                ),
+                response(
+                    "201 Created",
+                    &[format!("Location: {}/queue/item/7/", url)],
+                    "",
+                ),
+                json(r#"{"cancelled": false, "executable": null}"#),
+                json(&format!(
+                    r#"{{"executable": {{"number": 12, "url": "{}/job/nightly/12/"}}}}"#,
+                    url
+                )),
+                json(r#"{"result": null, "duration": 0}"#),
+                json(r#"{"result": "SUCCESS", "duration": 90000}"#),
+            ]
+        });
+        let parameters = [(String::from("BRANCH"), String::from("main"))];
+        let reports = run_build(&url, &parameters, Duration::from_secs(60));
+        assert_eq!(
+            reports,
+            vec![
+                format!("job 'nightly' on 'ci' queued: {}/queue/item/7/", url),
+                format!(
+                    "build #12 of job 'nightly' on 'ci' started: {}/job/nightly/12/",
+                    url
+                ),
+                String::from(
+                    "build #12 of job 'nightly' on 'ci' completed! Result: SUCCESS after 90 s"
+                ),
+            ]
+        );
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("get /crumbissuer/api/json "));
+        assert!(requests[1].starts_with("post /job/nightly/buildwithparameters?branch=main "));
+        assert!(requests[1].contains("\r\njenkins-crumb: c0ffee\r\n"));
+        assert!(requests[1].contains("\r\ncookie: jsessionid.1=abc\r\n"));
+        assert!(requests[2].starts_with("get /queue/item/7/api/json?tree="));
+        assert!(requests[4].starts_with("get /job/nightly/12/api/json?tree="));
+    }
+
+    #[test]
+    fn give_up_following_a_build() {
+        let (url, server) = serve(|url| {
+            vec![
+                not_found(),
+                response(
+                    "201 Created",
+                    &[format!("Location: {}/queue/item/7/", url)],
+                    "",
+                ),
+                json(&format!(
+                    r#"{{"executable": {{"number": 12, "url": "{}/job/nightly/12/"}}}}"#,
+                    url
+                )),
+                json(r#"{"result": null, "duration": 0}"#),
+            ]
+        });
+        let reports = run_build(&url, &[], Duration::from_secs(0));
+        assert_eq!(
+            reports.last().unwrap(),
+            "build #12 of job 'nightly' on 'ci' is still running after 0 s, \
+             no longer following it"
+        );
+        let requests = server.join().unwrap();
+        assert!(requests[1].starts_with("post /job/nightly/build "));
+        assert!(!requests[1].contains("jenkins-crumb"));
+
+        let (url, _) = serve(|url| {
+            vec![
+                not_found(),
+                response(
+                    "201 Created",
+                    &[format!("Location: {}/queue/item/7/", url)],
+                    "",
+                ),
+                json(r#"{"cancelled": false}"#),
+            ]
+        });
+        let reports = run_build(&url, &[], Duration::from_secs(0));
+        assert_eq!(
+            reports.last().unwrap(),
+            "job 'nightly' on 'ci' is still queued after 0 s, no longer following it"
+        );
+    }
+
+    #[test]
+    fn report_errors() {
+        let (url, _) = serve(|_| vec![response("403 Forbidden", &[], "")]);
+        let reports = run_build(&url, &[], Duration::from_secs(60));
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].starts_with("could not trigger job 'nightly' on 'ci': "));
+
+        let (url, _) = serve(|url| {
+            vec![
+                not_found(),
+                response(
+                    "201 Created",
+                    &[format!("Location: {}/queue/item/7/", url)],
+                    "",
+                ),
+                json(r#"{"cancelled": true}"#),
+            ]
+        });
+        let reports = run_build(&url, &[], Duration::from_secs(60));
+        assert_eq!(
+            reports.last().unwrap(),
+            "job 'nightly' on 'ci' did not start: the queue item was cancelled"
+        );
+    }
+
+    #[test]
+    fn abort_the_running_build() {
+        let (url, server) = serve(|_| {
+            vec![
+                json(r#"{"number": 5, "result": null}"#),
+                not_found(),
+                response("200 OK", &[], ""),
+            ]
+        });
+        let config = a_config(&url);
+        let aborted = abort(&Client::new(), &config.job[0], "nightly", None);
+        assert_eq!(aborted, Ok(BuildNumber(5)));
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("get /job/nightly/lastbuild/api/json?tree="));
+        assert!(requests[2].starts_with("post /job/nightly/5/stop "));
+
+        let (url, _) = serve(|_| vec![json(r#"{"number": 4, "result": "SUCCESS"}"#)]);
+        let config = a_config(&url);
+        let aborted = abort(
+            &Client::new(),
+            &config.job[0],
+            "nightly",
+            Some(BuildNumber(4)),
+        );
+        assert_eq!(
+            aborted,
+            Err(String::from(
+                "build #4 is not running, its result is SUCCESS"
+            ))
+        );
+    }
+}
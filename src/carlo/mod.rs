@@ -1,11 +1,16 @@
 mod irc;
 mod jenkins;
+mod status;
+mod template;
 
-use std::time::Instant;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::iter;
+use std::time::{Duration, Instant};
 
-use std::sync::mpsc;
+use std::sync::mpsc::{self, Receiver, Sender};
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use std::thread;
 
@@ -13,64 +18,103 @@ use ::irc::client::prelude::{Client, ClientExt, Command, IrcClient};
 use ::irc::proto::message::Message;
 use ::irc::proto::ChannelExt;
 
+use self::irc::format;
 use self::irc::IrcListener;
-use self::jenkins::cache::Name;
-use self::jenkins::{BuildDuration, BuildNumber, BuildUrl, JListener};
-use crate::config::Config;
+use self::jenkins::cache::{Cache, Name};
+use self::jenkins::event::{AbortEvent, BuildEvent, StartEvent};
+use self::jenkins::BuildNumber;
+use self::jenkins::JListener;
+pub use self::jenkins::{BuildResult, Transition};
+use self::status::StatusRequest;
+pub use self::template::Template;
+use crate::config::{Config, JenkinsConfig};
+
+/// A build, by server id, job name and build number
+type BuildKey = (String, String, BuildNumber);
 
 #[derive(Debug)]
 pub struct Carlo {
     start_time: Instant,
     client: Arc<IrcClient>,
-    jenkins_config: Option<Config>,
+    jenkins_config: Option<Arc<Config>>,
+    tx: Sender<Event>,
+    rx: Receiver<Event>,
+    /// Who aborted builds from IRC and when
+    aborted_by: RefCell<HashMap<BuildKey, (String, Instant)>>,
+    /// What the Jenkins poller knows about the jobs
+    cache: Arc<Mutex<Cache>>,
+    /// Events sent there are handled after the Jenkins poller has refreshed the cache
+    refresh_tx: Sender<Event>,
+    refresh_rx: Option<Receiver<Event>>,
+    /// When a refresh was last requested
+    last_refresh: Cell<Option<Instant>>,
 }
 
+/// How long to remember who aborted a build whose completion has not been reported, e.g.
+/// because its job is filtered out
+const ABORTED_BY_TTL: Duration = Duration::from_secs(3600);
+
+/// Minimum time between two refreshes requested by someone else than an owner
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Debug)]
 pub enum Event {
     IncomingIrcMessage(Message),
-    UpdatedJob(
-        String,
-        Name,
-        String,
-        BuildNumber,
-        BuildDuration,
-        BuildUrl,
-        Vec<String>,
-    ),
+    UpdatedJob(BuildEvent),
+    StartedJob(StartEvent),
+    AbortedJob(AbortEvent),
+    /// A message to send to a channel or a nick
+    Reply(String, String),
+    Status(StatusRequest),
+}
+
+impl Default for Carlo {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Carlo {
     pub fn new() -> Carlo {
         debug!("New Carlo instance");
+        let (tx, rx) = mpsc::channel();
+        let (refresh_tx, refresh_rx) = mpsc::channel();
         Carlo {
             start_time: Instant::now(),
             client: Arc::new(IrcClient::new("irc.toml").expect("Could not find irc.toml file")),
             jenkins_config: Config::from_file("jenkins.toml")
                 .map_err(|err| warn!("Config could not be read: {}", err))
-                .ok(),
+                .ok()
+                .map(Arc::new),
+            tx,
+            rx,
+            aborted_by: RefCell::new(HashMap::new()),
+            cache: Arc::new(Mutex::new(Cache::new())),
+            refresh_tx,
+            refresh_rx: Some(refresh_rx),
+            last_refresh: Cell::new(None),
         }
     }
 
     pub fn run(&mut self) {
-        let (tx, rx) = mpsc::channel();
-
         debug!("Identifying with server");
         self.client.identify().unwrap();
 
         let mut handles = Vec::new();
 
-        let irclistener = IrcListener::new(self.client.clone(), tx.clone());
+        let irclistener = IrcListener::new(self.client.clone(), self.tx.clone());
 
         handles.push(thread::spawn(move || irclistener.listen()));
 
-        if let Some(config) = self.jenkins_config.take() {
-            let mut jlistener = JListener::new(tx.clone());
-            handles.push(thread::spawn(move || jlistener.listen(config)));
+        if let (Some(config), Some(refresh)) = (self.jenkins_config.clone(), self.refresh_rx.take())
+        {
+            let mut jlistener = JListener::new(self.tx.clone(), self.cache.clone());
+            handles.push(thread::spawn(move || jlistener.listen(config, refresh)));
         }
 
-        rx.iter().for_each(|event| {
+        self.rx.iter().for_each(|event| {
             self.handle(event).into_iter().for_each(|message| {
-                info!("Sending {}", message);
+                info!("Sending {}", format::strip_formatting(&message.to_string()));
                 self.client.send(message).unwrap();
             });
         });
@@ -83,9 +127,11 @@ impl Carlo {
         debug!("Handling event {:?}", event);
         match event {
             Event::IncomingIrcMessage(message) => self.handle_irc(message),
-            Event::UpdatedJob(server, name, result, number, duration, url, notify) => {
-                self.handle_updated_job(server, name, result, number, duration, url, notify)
-            }
+            Event::UpdatedJob(build) => self.handle_updated_job(build),
+            Event::StartedJob(start) => self.handle_started_job(start),
+            Event::AbortedJob(abort) => self.handle_aborted_job(abort),
+            Event::Reply(target, text) => vec![Message::from(Command::PRIVMSG(target, text))],
+            Event::Status(request) => self.handle_status(request),
         }
     }
 
@@ -97,7 +143,13 @@ impl Carlo {
                 if !channel.is_channel_name() || msg.trim_start().starts_with(&cmd_prefix) {
                     let reply_to = message.response_target().unwrap().to_string();
                     let source_nick = message.source_nickname().unwrap_or("");
-                    self.process_msg(&source_nick, &reply_to, &msg)
+                    let command = msg
+                        .trim_start()
+                        .strip_prefix(&cmd_prefix)
+                        .map(|command| command.trim_start_matches([':', ',']))
+                        .unwrap_or(msg)
+                        .trim();
+                    self.process_msg(source_nick, &reply_to, command)
                 } else {
                     Vec::new()
                 }
@@ -106,69 +158,573 @@ impl Carlo {
         }
     }
 
-    fn handle_updated_job(
-        &self,
-        server: String,
-        name: Name,
-        result: String,
-        number: BuildNumber,
-        duration: BuildDuration,
-        url: BuildUrl,
-        notify: Vec<String>,
-    ) -> Vec<Message> {
-        debug!(
-            "Handling Job update {:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}",
-            server, name, result, number, duration, url, notify
-        );
-        notify
+    fn handle_updated_job(&self, mut build: BuildEvent) -> Vec<Message> {
+        debug!("Handling Job update {:?}", build);
+        let key = (build.server.clone(), build.name.0.clone(), build.number);
+        build.details.aborted_by = self
+            .aborted_by
+            .borrow_mut()
+            .remove(&key)
+            .map(|(nick, _)| nick);
+        let formatted = self.client.config().get_option("colors") == Some("true");
+        let reply = self
+            .jenkins_config
+            .as_ref()
+            .and_then(|config| config.template(&build.server, &build.name.0, build.transition))
+            .cloned()
+            .unwrap_or_else(|| Template::builtin(build.transition))
+            .render(&build, formatted);
+        let console: Vec<String> = build
+            .details
+            .console
+            .iter()
+            .map(|line| format::strip_formatting(line))
+            .collect();
+        build
+            .notify
             .into_iter()
-            .map(|dest| {
-                let reply = if result == "SUCCESS" {
-                    format!(
-                        "Build #{} for job '{}' on '{}'! Result: {}",
-                        number, name, server, result
-                    )
-                } else {
-                    format!(
-                        "Build #{} for job '{}' on '{}'! Result: {}, URL: {}",
-                        number, name, server, result, url
-                    )
-                };
-                let cmd = Command::PRIVMSG(dest, reply);
-                Message::from(cmd)
-            }).collect()
+            .flat_map(|dest| {
+                iter::once(reply.clone())
+                    .chain(console.iter().cloned())
+                    .map(move |line| Message::from(Command::PRIVMSG(dest.clone(), line)))
+            })
+            .collect()
+    }
+
+    fn handle_started_job(&self, start: StartEvent) -> Vec<Message> {
+        debug!("Handling Job start {:?}", start);
+        let reply = start.to_string();
+        start
+            .notify
+            .into_iter()
+            .map(|dest| Message::from(Command::PRIVMSG(dest, reply.clone())))
+            .collect()
+    }
+
+    fn handle_aborted_job(&self, abort: AbortEvent) -> Vec<Message> {
+        info!(
+            "{} aborted build #{} of job {} on {}",
+            abort.nick, abort.number, abort.name, abort.server
+        );
+        let reply = format!(
+            "{} aborted build #{} of job '{}' on '{}'",
+            abort.nick, abort.number, abort.name, abort.server
+        );
+        let key = (abort.server, abort.name.0, abort.number);
+        let mut aborted_by = self.aborted_by.borrow_mut();
+        aborted_by.retain(|_, (_, at)| at.elapsed() < ABORTED_BY_TTL);
+        aborted_by.insert(key, (abort.nick, Instant::now()));
+        vec![Message::from(Command::PRIVMSG(abort.reply_to, reply))]
     }
 
     fn process_msg(&self, source_nick: &str, reply_to: &str, incoming: &str) -> Vec<Message> {
-        if incoming.contains("uptime") {
-            info!(
-                "\"uptime\" command received from {} on {}",
-                source_nick, reply_to
-            );
-            let reply = format!("uptime = {} seconds", self.start_time.elapsed().as_secs());
-            let cmd = Command::PRIVMSG(reply_to.to_string(), reply);
-            return vec![Message::from(cmd)];
-        } else if incoming.starts_with("say ") {
-            info!(
-                "\"say\" command received from {} on {}",
-                source_nick, reply_to
+        let (command, args) = match incoming.split_once(char::is_whitespace) {
+            Some((command, args)) => (command, args.trim()),
+            None => (incoming, ""),
+        };
+        match command {
+            "uptime" => {
+                info!(
+                    "\"uptime\" command received from {} on {}",
+                    source_nick, reply_to
+                );
+                let reply = format!("uptime = {} seconds", self.start_time.elapsed().as_secs());
+                let cmd = Command::PRIVMSG(reply_to.to_string(), reply);
+                vec![Message::from(cmd)]
+            }
+            "say" => {
+                info!(
+                    "\"say\" command received from {} on {}",
+                    source_nick, reply_to
+                );
+                if !self.client.config().is_owner(source_nick) {
+                    return Vec::new();
+                }
+                match args.split_once(' ') {
+                    Some((chan, reply)) => {
+                        let cmd = Command::PRIVMSG(chan.to_string(), reply.trim().to_string());
+                        vec![Message::from(cmd)]
+                    }
+                    None => {
+                        debug!("\"say\" command has no message, not doing anything");
+                        Vec::new()
+                    }
+                }
+            }
+            "build" => self.build_command(source_nick, reply_to, args),
+            "abort" => self.abort_command(source_nick, reply_to, args),
+            "status" => self.status_command(source_nick, reply_to, args),
+            "failing" | "red" if args.is_empty() => {
+                info!(
+                    "\"{}\" command received from {} on {}",
+                    command, source_nick, reply_to
+                );
+                let lines = match self.jenkins_config {
+                    Some(ref config) => status::failing(&self.cache.lock().unwrap(), config),
+                    None => vec![String::from("no Jenkins server is configured")],
+                };
+                lines
+                    .into_iter()
+                    .map(|line| Message::from(Command::PRIVMSG(reply_to.to_string(), line)))
+                    .collect()
+            }
+            _ => {
+                debug!("unrecognized command: {}", incoming);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Whether `nick` may act on the Jenkins server described by `j_config`.
+    fn is_authorized(&self, nick: &str, j_config: &JenkinsConfig) -> bool {
+        self.client.config().is_owner(nick) || j_config.authorized.iter().any(|name| name == nick)
+    }
+
+    /// Handle `build <server> <job> [key=value ...]`: trigger the build in the background and
+    /// report its progress to the requester.
+    fn build_command(&self, source_nick: &str, reply_to: &str, args: &str) -> Vec<Message> {
+        info!(
+            "\"build\" command received from {} on {}",
+            source_nick, reply_to
+        );
+        let reply = |text: &str| {
+            let text = addressed(source_nick, reply_to, text);
+            vec![Message::from(Command::PRIVMSG(reply_to.to_string(), text))]
+        };
+        let config = match self.jenkins_config {
+            Some(ref config) => config.clone(),
+            None => return reply("no Jenkins server is configured"),
+        };
+        let (server, job, parameters) = match parse_build_args(args) {
+            Some(args) => args,
+            None => return reply("usage: build <server> <job> [key=value ...]"),
+        };
+        match config.jenkins(&server) {
+            Some(j_config) if self.is_authorized(source_nick, j_config) => (),
+            Some(_) => {
+                warn!(
+                    "{} is not allowed to trigger builds on {}",
+                    source_nick, server
+                );
+                return reply(&format!(
+                    "you are not allowed to trigger builds on '{}'",
+                    server
+                ));
+            }
+            None => return reply(&format!("unknown server '{}'", server)),
+        }
+        let tx = self.tx.clone();
+        let nick = source_nick.to_string();
+        let reply_to = reply_to.to_string();
+        thread::spawn(move || {
+            let j_config = config.jenkins(&server).unwrap();
+            let client = reqwest::blocking::Client::new();
+            let poll = Duration::from_secs(config.sleep);
+            let timeout = Duration::from_secs(config.build_timeout);
+            jenkins::remote::build(
+                &client,
+                j_config,
+                &job,
+                &parameters,
+                poll,
+                timeout,
+                |text| {
+                    let text = addressed(&nick, &reply_to, &text);
+                    tx.send(Event::Reply(reply_to.clone(), text)).unwrap();
+                },
             );
-            if !self.client.config().is_owner(source_nick) {
-                return Vec::new();
+        });
+        Vec::new()
+    }
+
+    /// Handle `status [refresh] <server|job-pattern> [page]`: list what is known about the
+    /// jobs, after polling Jenkins if `refresh` is given and allowed by `may_refresh`.
+    fn status_command(&self, source_nick: &str, reply_to: &str, args: &str) -> Vec<Message> {
+        info!(
+            "\"status\" command received from {} on {}",
+            source_nick, reply_to
+        );
+        let request = match StatusRequest::parse(source_nick, reply_to, args) {
+            Some(request) => request,
+            None => {
+                let text = addressed(source_nick, reply_to, StatusRequest::USAGE);
+                return vec![Message::from(Command::PRIVMSG(reply_to.to_string(), text))];
             }
-            let v: Vec<&str> = incoming[4..].trim().splitn(2, ' ').collect();
-            if v.len() <= 1 {
-                debug!("\"say\" command has no message, not doing anything");
-                return Vec::new();
+        };
+        if request.refresh && self.jenkins_config.is_some() {
+            if !self.may_refresh(source_nick) {
+                info!(
+                    "Not refreshing for {}, the last refresh was less than {} s ago",
+                    source_nick,
+                    REFRESH_INTERVAL.as_secs()
+                );
+            } else if let Err(err) = self.refresh_tx.send(Event::Status(request.clone())) {
+                error!("Could not ask the Jenkins poller for a refresh: {}", err);
+                let text = addressed(
+                    source_nick,
+                    reply_to,
+                    "could not refresh the jobs, the Jenkins poller is not running",
+                );
+                return vec![Message::from(Command::PRIVMSG(reply_to.to_string(), text))];
             } else {
-                let chan = v[0].to_string();
-                let reply = v[1].trim().to_string();
-                let cmd = Command::PRIVMSG(chan, reply);
-                return vec![Message::from(cmd)];
+                return Vec::new();
             }
-        } else {
-            debug!("unrecognized command: {}", incoming);
         }
+        self.handle_status(request)
+    }
+
+    /// Whether `nick` may make the Jenkins poller refresh the jobs now: owners always may,
+    /// anyone else only if the last refresh was at least `REFRESH_INTERVAL` ago.
+    fn may_refresh(&self, nick: &str) -> bool {
+        let recent = self
+            .last_refresh
+            .get()
+            .is_some_and(|at| at.elapsed() < REFRESH_INTERVAL);
+        if recent && !self.client.config().is_owner(nick) {
+            return false;
+        }
+        self.last_refresh.set(Some(Instant::now()));
+        true
+    }
+
+    fn handle_status(&self, request: StatusRequest) -> Vec<Message> {
+        let lines = match self.jenkins_config {
+            Some(ref config) => request.answer(&self.cache.lock().unwrap(), config),
+            None => vec![String::from("no Jenkins server is configured")],
+        };
+        lines
+            .into_iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let line = if i == 0 {
+                    addressed(&request.nick, &request.reply_to, &line)
+                } else {
+                    line
+                };
+                Message::from(Command::PRIVMSG(request.reply_to.clone(), line))
+            })
+            .collect()
+    }
+
+    /// Handle `abort <server> <job> [number]`: abort the build in the background, and confirm
+    /// it where it was requested.
+    fn abort_command(&self, source_nick: &str, reply_to: &str, args: &str) -> Vec<Message> {
+        info!(
+            "\"abort\" command received from {} on {}",
+            source_nick, reply_to
+        );
+        let reply = |text: &str| {
+            let text = addressed(source_nick, reply_to, text);
+            vec![Message::from(Command::PRIVMSG(reply_to.to_string(), text))]
+        };
+        let config = match self.jenkins_config {
+            Some(ref config) => config.clone(),
+            None => return reply("no Jenkins server is configured"),
+        };
+        let (server, job, number) = match parse_abort_args(args) {
+            Some(args) => args,
+            None => return reply("usage: abort <server> <job> [number]"),
+        };
+        match config.jenkins(&server) {
+            Some(j_config) if self.is_authorized(source_nick, j_config) => (),
+            Some(_) => {
+                warn!(
+                    "{} is not allowed to abort builds on {}",
+                    source_nick, server
+                );
+                return reply(&format!(
+                    "you are not allowed to abort builds on '{}'",
+                    server
+                ));
+            }
+            None => return reply(&format!("unknown server '{}'", server)),
+        }
+        let tx = self.tx.clone();
+        let nick = source_nick.to_string();
+        let reply_to = reply_to.to_string();
+        thread::spawn(move || {
+            let j_config = config.jenkins(&server).unwrap();
+            let client = reqwest::blocking::Client::new();
+            let event = match jenkins::remote::abort(&client, j_config, &job, number) {
+                Ok(number) => Event::AbortedJob(AbortEvent {
+                    server,
+                    name: Name(job),
+                    number,
+                    nick,
+                    reply_to,
+                }),
+                Err(err) => {
+                    let text = format!("could not abort job '{}' on '{}': {}", job, server, err);
+                    Event::Reply(reply_to.clone(), addressed(&nick, &reply_to, &text))
+                }
+            };
+            tx.send(event).unwrap();
+        });
         Vec::new()
     }
 }
+
+/// Build parameters, as name and value pairs
+type Parameters = Vec<(String, String)>;
+
+/// Split the arguments of the `build` command into the server, the job and the build
+/// parameters, or return `None` if they are malformed.
+fn parse_build_args(args: &str) -> Option<(String, String, Parameters)> {
+    let mut args = args.split_whitespace();
+    let server = args.next()?.to_string();
+    let job = args.next()?.to_string();
+    let parameters = args
+        .map(|arg| {
+            arg.split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some((server, job, parameters))
+}
+
+/// Split the arguments of the `abort` command into the server, the job and the build number,
+/// which may be written `#42`, or return `None` if they are malformed.
+fn parse_abort_args(args: &str) -> Option<(String, String, Option<BuildNumber>)> {
+    let args: Vec<&str> = args.split_whitespace().collect();
+    match args[..] {
+        [server, job] => Some((server.to_string(), job.to_string(), None)),
+        [server, job, number] => {
+            let number = number.trim_start_matches('#').parse().ok()?;
+            Some((
+                server.to_string(),
+                job.to_string(),
+                Some(BuildNumber(number)),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Prefix `text` with `nick` unless it is sent privately to them.
+fn addressed(nick: &str, reply_to: &str, text: &str) -> String {
+    if nick == reply_to {
+        text.to_string()
+    } else {
+        format!("{}: {}", nick, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r##"
+sleep = 60
+
+[[job]]
+server = "http://localhost:1"
+id = "ci"
+user = "carlo"
+token = "secret"
+notify = ["#builds"]
+"##;
+
+    fn a_carlo(config: Option<&str>) -> Carlo {
+        let irc_config = ::irc::client::data::Config {
+            nickname: Some(String::from("carlo")),
+            server: Some(String::from("irc.example.com")),
+            owners: Some(vec![String::from("boss")]),
+            use_mock_connection: Some(true),
+            ..Default::default()
+        };
+        let (tx, rx) = mpsc::channel();
+        let (refresh_tx, refresh_rx) = mpsc::channel();
+        Carlo {
+            start_time: Instant::now(),
+            client: Arc::new(IrcClient::from_config(irc_config).unwrap()),
+            jenkins_config: config.map(|config| Arc::new(Config::from_string(config).unwrap())),
+            tx,
+            rx,
+            aborted_by: RefCell::new(HashMap::new()),
+            cache: Arc::new(Mutex::new(Cache::new())),
+            refresh_tx,
+            refresh_rx: Some(refresh_rx),
+            last_refresh: Cell::new(None),
+        }
+    }
+
+    /// The target and the text of the PRIVMSGs sent in reply to `incoming`.
+    fn replies(carlo: &Carlo, nick: &str, incoming: &str) -> Vec<(String, String)> {
+        carlo
+            .process_msg(nick, "#chan", incoming)
+            .into_iter()
+            .map(|message| match message.command {
+                Command::PRIVMSG(target, text) => (target, text),
+                command => panic!("unexpected command {:?}", command),
+            })
+            .collect()
+    }
+
+    fn reply(text: &str) -> Vec<(String, String)> {
+        vec![(String::from("#chan"), String::from(text))]
+    }
+
+    #[test]
+    fn dispatch_uptime() {
+        let carlo = a_carlo(None);
+        assert_eq!(
+            replies(&carlo, "alice", "uptime"),
+            reply("uptime = 0 seconds")
+        );
+        assert_eq!(replies(&carlo, "alice", "uptimes"), vec![]);
+        assert_eq!(
+            replies(&carlo, "alice", "build ci uptime-monitor"),
+            reply("alice: no Jenkins server is configured")
+        );
+        assert_eq!(
+            replies(&carlo, "alice", "status uptime-*"),
+            reply("alice: no Jenkins server is configured")
+        );
+    }
+
+    #[test]
+    fn dispatch_say() {
+        let carlo = a_carlo(None);
+        assert_eq!(
+            replies(&carlo, "boss", "say #other  hello there "),
+            vec![(String::from("#other"), String::from("hello there"))]
+        );
+        assert_eq!(replies(&carlo, "boss", "say #other"), vec![]);
+        assert_eq!(replies(&carlo, "alice", "say #other hello"), vec![]);
+        assert_eq!(replies(&carlo, "boss", "sayonara #other hello"), vec![]);
+    }
+
+    #[test]
+    fn dispatch_build_and_abort() {
+        let carlo = a_carlo(Some(CONFIG));
+        assert_eq!(
+            replies(&carlo, "alice", "build"),
+            reply("alice: usage: build <server> <job> [key=value ...]")
+        );
+        assert_eq!(
+            replies(&carlo, "alice", "build ci uptime-monitor"),
+            reply("alice: you are not allowed to trigger builds on 'ci'")
+        );
+        assert_eq!(
+            replies(&carlo, "boss", "build other nightly"),
+            reply("boss: unknown server 'other'")
+        );
+        assert_eq!(
+            replies(&carlo, "alice", "abort ci nightly"),
+            reply("alice: you are not allowed to abort builds on 'ci'")
+        );
+        assert_eq!(replies(&carlo, "alice", "builder ci nightly"), vec![]);
+    }
+
+    #[test]
+    fn dispatch_status_and_failing() {
+        let carlo = a_carlo(Some(CONFIG));
+        assert_eq!(
+            replies(&carlo, "alice", "status"),
+            reply(&format!("alice: {}", StatusRequest::USAGE))
+        );
+        assert_eq!(
+            replies(&carlo, "alice", "failing"),
+            reply("all jobs are green")
+        );
+        assert_eq!(replies(&carlo, "alice", "red"), reply("all jobs are green"));
+        assert_eq!(replies(&carlo, "alice", "red alert"), vec![]);
+    }
+
+    #[test]
+    fn rate_limit_refreshes() {
+        let mut carlo = a_carlo(Some(CONFIG));
+        assert_eq!(replies(&carlo, "alice", "status refresh ci"), vec![]);
+        let refresh = carlo.refresh_rx.as_ref().unwrap();
+        assert!(matches!(refresh.try_recv(), Ok(Event::Status(_))));
+        assert_eq!(
+            replies(&carlo, "bob", "status refresh ci"),
+            reply("bob: no known job matches 'ci'")
+        );
+        assert_eq!(replies(&carlo, "boss", "status refresh ci"), vec![]);
+
+        // The Jenkins poller has stopped
+        carlo.refresh_rx = None;
+        carlo.last_refresh.set(None);
+        assert_eq!(
+            replies(&carlo, "alice", "status refresh ci"),
+            reply("alice: could not refresh the jobs, the Jenkins poller is not running")
+        );
+    }
+
+    #[test]
+    fn parse_abort_arguments() {
+        let job = |number: Option<u32>| {
+            Some((
+                String::from("ci"),
+                String::from("team/nightly"),
+                number.map(BuildNumber),
+            ))
+        };
+        assert_eq!(parse_abort_args("ci team/nightly"), job(None));
+        assert_eq!(parse_abort_args(" ci team/nightly 42 "), job(Some(42)));
+        assert_eq!(parse_abort_args("ci team/nightly #42"), job(Some(42)));
+        assert_eq!(parse_abort_args(""), None);
+        assert_eq!(parse_abort_args("ci"), None);
+        assert_eq!(parse_abort_args("ci team/nightly last"), None);
+        assert_eq!(parse_abort_args("ci team/nightly -1"), None);
+        assert_eq!(parse_abort_args("ci team/nightly 42 now"), None);
+    }
+
+    #[test]
+    fn remember_who_aborted_builds() {
+        let carlo = a_carlo(None);
+        let abort = |number| AbortEvent {
+            server: String::from("jenkins"),
+            name: Name(String::from("nightly")),
+            number: BuildNumber(number),
+            nick: String::from("alice"),
+            reply_to: String::from("#chan"),
+        };
+        assert_eq!(
+            carlo.handle_aborted_job(abort(42))[0].to_string(),
+            "PRIVMSG #chan :alice aborted build #42 of job 'nightly' on 'jenkins'\r\n"
+        );
+        let notification = |carlo: &Carlo| {
+            let build = jenkins::event::tests::build_event();
+            carlo.handle_updated_job(build)[0].to_string()
+        };
+        assert!(notification(&carlo).ends_with(" (aborted by alice)\r\n"));
+        assert!(carlo.aborted_by.borrow().is_empty());
+        assert!(!notification(&carlo).contains("aborted by"));
+
+        // The completion of build #41 is never reported
+        carlo.handle_aborted_job(abort(41));
+        let expired = Instant::now().checked_sub(ABORTED_BY_TTL).unwrap();
+        carlo
+            .aborted_by
+            .borrow_mut()
+            .values_mut()
+            .for_each(|(_, at)| *at = expired);
+        carlo.handle_aborted_job(abort(42));
+        assert_eq!(carlo.aborted_by.borrow().len(), 1);
+    }
+
+    #[test]
+    fn parse_build_arguments() {
+        assert_eq!(
+            parse_build_args("ci nightly"),
+            Some((String::from("ci"), String::from("nightly"), vec![]))
+        );
+        assert_eq!(
+            parse_build_args(" ci  team/nightly BRANCH=main  ARGS=a=b EMPTY="),
+            Some((
+                String::from("ci"),
+                String::from("team/nightly"),
+                vec![
+                    (String::from("BRANCH"), String::from("main")),
+                    (String::from("ARGS"), String::from("a=b")),
+                    (String::from("EMPTY"), String::new()),
+                ]
+            ))
+        );
+        assert_eq!(parse_build_args(""), None);
+        assert_eq!(parse_build_args("ci"), None);
+        assert_eq!(parse_build_args("ci nightly BRANCH"), None);
+    }
+}
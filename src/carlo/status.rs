@@ -0,0 +1,308 @@
+//! Answers to the `status` and `failing` commands, from what the Jenkins poller knows about
+//! the jobs.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::jenkins::cache::{Cache, Timestamp};
+use crate::config::{Config, Pattern};
+
+/// A `status` command, answered from the cache once it is up to date if `refresh` is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusRequest {
+    pub nick: String,
+    pub reply_to: String,
+    /// A server id, or a pattern matching job names
+    pub target: String,
+    /// Starting at 1
+    pub page: usize,
+    pub refresh: bool,
+}
+
+impl StatusRequest {
+    pub const USAGE: &'static str = "usage: status [refresh] <server|job-pattern> [page]";
+
+    /// Parse the arguments of `status [refresh] <server|job-pattern> [page]`.
+    pub fn parse(nick: &str, reply_to: &str, args: &str) -> Option<StatusRequest> {
+        let mut args: Vec<&str> = args.split_whitespace().collect();
+        let refresh = args.first() == Some(&"refresh") && args.len() > 1;
+        if refresh {
+            args.remove(0);
+        }
+        let (target, page) = match args[..] {
+            [target] => (target, 1),
+            [target, page] => (target, page.parse().ok().filter(|page| *page > 0)?),
+            _ => return None,
+        };
+        Some(StatusRequest {
+            nick: nick.to_string(),
+            reply_to: reply_to.to_string(),
+            target: target.to_string(),
+            page,
+            refresh,
+        })
+    }
+
+    /// The reply to the request: the requested page of the status of the matching jobs.
+    pub fn answer(&self, cache: &Cache, config: &Config) -> Vec<String> {
+        match job_lines(cache, config, &self.target, now()) {
+            Ok(ref lines) if lines.is_empty() => {
+                vec![format!("no known job matches '{}'", self.target)]
+            }
+            Ok(lines) => {
+                let next = format!("status {} {}", self.target, self.page + 1);
+                paginate(lines, self.page, config.status_lines, &next)
+            }
+            Err(err) => vec![err],
+        }
+    }
+}
+
+/// The current time, in milliseconds since the epoch like Jenkins timestamps.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// How long ago `timestamp` was, e.g. `3 h`.
+fn age(now: u64, timestamp: Timestamp) -> String {
+    let minutes = now.saturating_sub(timestamp.0) / 60_000;
+    match (minutes / (24 * 60), minutes / 60) {
+        (0, 0) => format!("{} min", minutes),
+        (0, hours) => format!("{} h", hours),
+        (days, _) => format!("{} d", days),
+    }
+}
+
+/// Maximum number of failing jobs listed on a single line
+const FAILING_PER_LINE: usize = 5;
+
+/// The reply to the `failing` command: the jobs whose last completed build was not
+/// successful, grouped by server and sorted from the longest broken.
+pub fn failing(cache: &Cache, config: &Config) -> Vec<String> {
+    failing_lines(cache, config, now())
+}
+
+fn failing_lines(cache: &Cache, config: &Config, now: u64) -> Vec<String> {
+    let mut lines = Vec::new();
+    config.job.iter().for_each(|j_config| {
+        let mut jobs: Vec<_> = cache
+            .entries()
+            .filter(|(server, _, entry)| {
+                **server == j_config.id
+                    && entry.outcome().is_some_and(|result| !result.is_success())
+            })
+            .map(|(_, name, entry)| (entry.broken_since, name, entry))
+            .collect();
+        jobs.sort_by(|(since_a, name_a, _), (since_b, name_b, _)| {
+            (since_a, &name_a.0).cmp(&(since_b, &name_b.0))
+        });
+        let jobs: Vec<String> = jobs
+            .into_iter()
+            .map(|(since, name, entry)| {
+                let result = entry.outcome().map_or("UNKNOWN", |result| result.as_str());
+                let mut line = format!("{} #{} {}", name, entry.last_completed, result);
+                // unknown if the job was already failing when it was first seen
+                if let Some(since) = since {
+                    line.push_str(&format!(" for {}", age(now, since)));
+                }
+                line
+            })
+            .collect();
+        jobs.chunks(FAILING_PER_LINE)
+            .for_each(|chunk| lines.push(format!("{}: {}", j_config.id, chunk.join(", "))));
+    });
+    if lines.is_empty() {
+        lines.push(String::from("all jobs are green"));
+    }
+    lines
+}
+
+/// One line per known job of the server `target`, or per job matching the pattern `target`,
+/// sorted by server and job name.
+fn job_lines(
+    cache: &Cache,
+    config: &Config,
+    target: &str,
+    now: u64,
+) -> Result<Vec<String>, String> {
+    let pattern = match config.jenkins(target) {
+        Some(_) => None,
+        None => Some(Pattern::parse(target)?),
+    };
+    let mut entries: Vec<_> = cache
+        .entries()
+        .filter(|(server, name, entry)| {
+            let matches = match pattern {
+                Some(ref pattern) => pattern.matches(&name.0),
+                None => *server == target,
+            };
+            // jobs whose first build is still running have no status yet
+            matches && entry.last_completed.0 > 0
+        })
+        .collect();
+    entries.sort_by(|(server_a, name_a, _), (server_b, name_b, _)| {
+        (server_a, &name_a.0).cmp(&(server_b, &name_b.0))
+    });
+    Ok(entries
+        .into_iter()
+        .map(|(server, name, entry)| {
+            let result = entry
+                .result
+                .as_ref()
+                .map_or("UNKNOWN", |result| result.as_str());
+            let mut line = format!(
+                "{}/{} #{}: {}, {} ago",
+                server,
+                name,
+                entry.last_completed,
+                result,
+                age(now, entry.timestamp)
+            );
+            if let Some(ref url) = entry.url {
+                line.push_str(&format!(", {}", url));
+            }
+            line
+        })
+        .collect())
+}
+
+/// Return page `page` of `lines`, with `per_page` lines per page. If more lines follow, a
+/// last line tells how to get them with the `next` command.
+fn paginate(lines: Vec<String>, page: usize, per_page: usize, next: &str) -> Vec<String> {
+    let per_page = per_page.max(1);
+    let pages = lines.len().div_ceil(per_page);
+    if page > pages {
+        return vec![format!("there are only {} pages", pages)];
+    }
+    let remaining = lines.len().saturating_sub(page * per_page);
+    let mut reply: Vec<String> = lines
+        .into_iter()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .collect();
+    if page < pages {
+        reply.push(format!(
+            "... {} more, say \"{}\" for the next page",
+            remaining, next
+        ));
+    }
+    reply
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carlo::jenkins::cache::{Entry, Name};
+    use crate::carlo::jenkins::{BuildNumber, BuildResult, BuildUrl};
+
+    const HOUR: u64 = 3_600_000;
+
+    fn a_cache() -> Cache {
+        let mut cache = Cache::new();
+        let mut add = |server: &str, name: &str, number: u32, result: Option<&str>, age: u64| {
+            let url = format!("http://{}/job/{}/{}/", server, name, number);
+            let broken_since = match result {
+                Some("FAILURE") => Some(Timestamp(100 * HOUR - 5 * age)),
+                _ => None,
+            };
+            let entry = Entry {
+                last_completed: BuildNumber(number),
+                timestamp: Timestamp(100 * HOUR - age),
+                result: result.map(BuildResult::from),
+                url: Some(BuildUrl(url)),
+                broken_since,
+                ..Entry::default()
+            };
+            cache.insert(&server.to_string(), &Name(name.to_string()), &entry);
+        };
+        add("ci", "nightly", 12, Some("FAILURE"), 3 * HOUR);
+        add("ci", "docs", 3, Some("SUCCESS"), 60_000);
+        add("ci", "release", 0, None, 0);
+        add("other", "nightly-arm", 7, None, 50 * HOUR);
+        add("ci", "tests", 5, Some("FAILURE"), HOUR);
+        add("ci", "lint", 9, Some("UNSTABLE"), 10 * HOUR);
+        cache
+    }
+
+    fn a_config() -> Config {
+        Config::from_string(
+            r##"
+sleep = 60
+status_lines = 2
+
+[[job]]
+server = "http://ci"
+id = "ci"
+user = "carlo"
+token = "secret"
+notify = ["#chan"]
+"##,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_requests() {
+        let parse = |args| StatusRequest::parse("alice", "#chan", args);
+        let request = parse("refresh nightly* 2").unwrap();
+        assert_eq!(request.target, "nightly*");
+        assert_eq!(request.page, 2);
+        assert!(request.refresh);
+        assert!(!parse("refresh").unwrap().refresh);
+        assert!(parse("ci 0").is_none());
+        assert!(parse("ci 1 2").is_none());
+        assert!(parse("").is_none());
+    }
+
+    #[test]
+    fn list_jobs() {
+        let (cache, config) = (a_cache(), a_config());
+        assert_eq!(
+            job_lines(&cache, &config, "ci", 100 * HOUR).unwrap(),
+            vec![
+                "ci/docs #3: SUCCESS, 1 min ago, http://ci/job/docs/3/",
+                "ci/lint #9: UNSTABLE, 10 h ago, http://ci/job/lint/9/",
+                "ci/nightly #12: FAILURE, 3 h ago, http://ci/job/nightly/12/",
+                "ci/tests #5: FAILURE, 1 h ago, http://ci/job/tests/5/",
+            ]
+        );
+        assert_eq!(
+            job_lines(&cache, &config, "nightly*", 100 * HOUR).unwrap(),
+            vec![
+                "ci/nightly #12: FAILURE, 3 h ago, http://ci/job/nightly/12/",
+                "other/nightly-arm #7: UNKNOWN, 2 d ago, http://other/job/nightly-arm/7/",
+            ]
+        );
+        assert!(job_lines(&cache, &config, "/(/", 0).is_err());
+    }
+
+    #[test]
+    fn list_failing_jobs() {
+        let (mut cache, config) = (a_cache(), a_config());
+        assert_eq!(
+            failing_lines(&cache, &config, 100 * HOUR),
+            vec!["ci: lint #9 UNSTABLE, nightly #12 FAILURE for 15 h, tests #5 FAILURE for 5 h"]
+        );
+        cache.prune_except(&String::from("ci"), &[]);
+        assert_eq!(
+            failing_lines(&cache, &config, 100 * HOUR),
+            vec!["all jobs are green"]
+        );
+    }
+
+    #[test]
+    fn paginate_lines() {
+        let lines: Vec<String> = (1..=5).map(|n| n.to_string()).collect();
+        assert_eq!(
+            paginate(lines.clone(), 1, 2, "status x 2"),
+            vec!["1", "2", "... 3 more, say \"status x 2\" for the next page"]
+        );
+        assert_eq!(paginate(lines.clone(), 3, 2, "status x 4"), vec!["5"]);
+        assert_eq!(
+            paginate(lines, 4, 2, "status x 5"),
+            vec!["there are only 3 pages"]
+        );
+    }
+}
@@ -0,0 +1,396 @@
+use std::convert::TryFrom;
+
+use super::irc::format::{self, Color};
+use super::jenkins::event::{self, BuildEvent};
+use super::jenkins::{BuildResult, Transition};
+
+/// A value that can be substituted in a template.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Field {
+    Job,
+    Server,
+    Number,
+    Result,
+    Transition,
+    Duration,
+    Url,
+    Culprits,
+    Causes,
+    Parameters,
+    Tests,
+    FailedTests,
+    Stages,
+    Details,
+    Started,
+}
+
+impl Field {
+    const ALL: [(&'static str, Field); 15] = [
+        ("job", Field::Job),
+        ("server", Field::Server),
+        ("number", Field::Number),
+        ("result", Field::Result),
+        ("transition", Field::Transition),
+        ("duration", Field::Duration),
+        ("url", Field::Url),
+        ("culprits", Field::Culprits),
+        ("causes", Field::Causes),
+        ("parameters", Field::Parameters),
+        ("tests", Field::Tests),
+        ("failed_tests", Field::FailedTests),
+        ("stages", Field::Stages),
+        ("details", Field::Details),
+        ("started", Field::Started),
+    ];
+
+    fn from_name(name: &str) -> Option<Field> {
+        Field::ALL
+            .iter()
+            .find(|(field_name, _)| *field_name == name)
+            .map(|(_, field)| *field)
+    }
+
+    /// Render the field for `build`, with mIRC formatting if `formatted` is true.
+    fn render(self, build: &BuildEvent, formatted: bool) -> String {
+        match self {
+            Field::Job if formatted => format::bold(&build.name.0),
+            Field::Job => build.name.to_string(),
+            Field::Server => build.server.clone(),
+            Field::Number => build.number.to_string(),
+            Field::Result => match result_color(&build.result) {
+                Some(color) if formatted => format::colored(build.result.as_str(), color),
+                _ => build.result.to_string(),
+            },
+            Field::Transition if formatted => {
+                let color = match build.transition {
+                    Transition::Broken | Transition::StillFailing => Color::Red,
+                    Transition::Fixed | Transition::StillGreen => Color::Green,
+                    Transition::Interrupted => Color::Grey,
+                };
+                format::colored(&build.transition.to_string(), color)
+            }
+            Field::Transition => build.transition.to_string(),
+            Field::Duration => build.duration.to_string(),
+            Field::Url => build.url.to_string(),
+            Field::Culprits => build.details.culprits.join(", "),
+            Field::Causes => build.details.causes.join(", "),
+            Field::Parameters => {
+                let parameters: Vec<String> = build
+                    .details
+                    .parameters
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                parameters.join(" ")
+            }
+            Field::Tests => build
+                .details
+                .tests
+                .map(|tests| tests.to_string())
+                .unwrap_or_default(),
+            Field::FailedTests => build.details.failed_tests.join(", "),
+            Field::Stages => {
+                let stages: Vec<String> = build
+                    .details
+                    .failed_stages
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+                stages.join(", ")
+            }
+            Field::Details => build.details.to_string(),
+            Field::Started => build.started.map(event::time_of_day).unwrap_or_default(),
+        }
+    }
+}
+
+fn result_color(result: &BuildResult) -> Option<Color> {
+    match result {
+        BuildResult::Success => Some(Color::Green),
+        BuildResult::Unstable => Some(Color::Yellow),
+        BuildResult::Failure => Some(Color::Red),
+        BuildResult::Aborted | BuildResult::NotBuilt => Some(Color::Grey),
+        BuildResult::Unknown(_) => None,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Segment {
+    Literal(String),
+    Field(Field),
+    /// Only rendered if all the fields it contains are non-empty
+    Optional(Vec<Segment>),
+}
+
+/// A notification message with placeholders, such as `"{job} is {transition}!"`.
+///
+/// Literal braces are written `{{` and `}}`. Text between `{?` and `?}` is only rendered if
+/// none of the placeholders it contains is empty.
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(try_from = "String")]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, String> {
+        let error = |msg: String| format!("invalid template \"{}\": {}", template, msg);
+        let mut stack: Vec<Vec<Segment>> = vec![Vec::new()];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    literal.push(c);
+                }
+                ('{', Some('?')) => {
+                    chars.next();
+                    if stack.len() > 1 {
+                        return Err(error(String::from("nested optional sections")));
+                    }
+                    Template::push_literal(&mut stack, &mut literal);
+                    stack.push(Vec::new());
+                }
+                ('?', Some('}')) if stack.len() > 1 => {
+                    chars.next();
+                    Template::push_literal(&mut stack, &mut literal);
+                    let optional = stack.pop().unwrap();
+                    stack.last_mut().unwrap().push(Segment::Optional(optional));
+                }
+                ('{', _) => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => {
+                                return Err(error(format!("unterminated placeholder {{{}", name)))
+                            }
+                        }
+                    }
+                    let field = Field::from_name(name.trim()).ok_or_else(|| {
+                        let names: Vec<&str> = Field::ALL.iter().map(|(name, _)| *name).collect();
+                        error(format!(
+                            "unknown placeholder {{{}}}, expected one of {}",
+                            name,
+                            names.join(", ")
+                        ))
+                    })?;
+                    Template::push_literal(&mut stack, &mut literal);
+                    stack.last_mut().unwrap().push(Segment::Field(field));
+                }
+                ('}', _) => return Err(error(String::from("unmatched '}', use '}}'"))),
+                _ => literal.push(c),
+            }
+        }
+        if stack.len() > 1 {
+            return Err(error(String::from("unterminated optional section")));
+        }
+        Template::push_literal(&mut stack, &mut literal);
+        Ok(Template {
+            segments: stack.pop().unwrap(),
+        })
+    }
+
+    fn push_literal(stack: &mut [Vec<Segment>], literal: &mut String) {
+        if !literal.is_empty() {
+            let segment = Segment::Literal(std::mem::take(literal));
+            stack.last_mut().unwrap().push(segment);
+        }
+    }
+
+    /// Whether the template shows the causes or the parameters of the build, which are only
+    /// fetched for successful builds if they are needed.
+    pub fn uses_causes_or_parameters(&self) -> bool {
+        fn uses(segments: &[Segment]) -> bool {
+            segments.iter().any(|segment| match segment {
+                Segment::Field(field) => *field == Field::Causes || *field == Field::Parameters,
+                Segment::Optional(optional) => uses(optional),
+                Segment::Literal(_) => false,
+            })
+        }
+        uses(&self.segments)
+    }
+
+    /// Render the template for `build`. If `formatted` is true, the job name and the result
+    /// are highlighted with mIRC formatting codes; otherwise, any formatting is stripped.
+    pub fn render(&self, build: &BuildEvent, formatted: bool) -> String {
+        let rendered =
+            Template::render_segments(&self.segments, build, formatted, false).unwrap_or_default();
+        if formatted {
+            rendered
+        } else {
+            format::strip_formatting(&rendered)
+        }
+    }
+
+    /// Render `segments`. If `strict` is true, return `None` as soon as one of the fields is
+    /// empty.
+    fn render_segments(
+        segments: &[Segment],
+        build: &BuildEvent,
+        formatted: bool,
+        strict: bool,
+    ) -> Option<String> {
+        let mut rendered = String::new();
+        for segment in segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Field(field) => {
+                    let value = field.render(build, formatted);
+                    if strict && value.is_empty() {
+                        return None;
+                    }
+                    rendered.push_str(&value);
+                }
+                Segment::Optional(optional) => {
+                    if let Some(value) = Template::render_segments(optional, build, formatted, true)
+                    {
+                        rendered.push_str(&value);
+                    }
+                }
+            }
+        }
+        Some(rendered)
+    }
+
+    /// The message used when no template has been configured.
+    pub fn builtin(transition: Transition) -> Template {
+        let template = match transition {
+            Transition::StillGreen => {
+                "Build #{number} for job '{job}' on '{server}'{? (started at {started})?}! \
+                 Result: {result}"
+            }
+            Transition::Fixed => {
+                "Build #{number} for job '{job}' on '{server}'{? (started at {started})?} \
+                 is fixed! Result: {result}"
+            }
+            Transition::Broken | Transition::StillFailing => {
+                "Build #{number} for job '{job}' on '{server}'{? (started at {started})?} \
+                 is {transition}! Result: {result}, URL: {url}{?, culprits: {culprits}?}\
+                 {? ({details})?}"
+            }
+            Transition::Interrupted => {
+                "Build #{number} for job '{job}' on '{server}'{? (started at {started})?} \
+                 was interrupted! Result: {result}{? ({details})?}"
+            }
+        };
+        Template::parse(template).expect("invalid builtin template")
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(template: String) -> Result<Template, String> {
+        Template::parse(&template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::jenkins::event::tests::build_event;
+    use super::super::jenkins::event::Change;
+    use super::*;
+
+    #[test]
+    fn render_fields() {
+        let template = Template::parse("{{{job}}} #{number}: {result} ({duration})").unwrap();
+        assert_eq!(
+            template.render(&build_event(), false),
+            "{nightly} #42: FAILURE (90 s)"
+        );
+    }
+
+    #[test]
+    fn render_optional_sections() {
+        let template = Template::parse("{job}{? by {culprits}?}: {culprits}!").unwrap();
+        let mut build = build_event();
+        assert_eq!(template.render(&build, false), "nightly: !");
+        build.details.culprits = vec![String::from("alice"), String::from("bob")];
+        assert_eq!(
+            template.render(&build, false),
+            "nightly by alice, bob: alice, bob!"
+        );
+    }
+
+    #[test]
+    fn render_causes_and_parameters() {
+        let template = Template::parse("{job}{? ({causes})?}{? with {parameters}?}").unwrap();
+        assert!(template.uses_causes_or_parameters());
+        assert!(!Template::parse("{job}: {result}")
+            .unwrap()
+            .uses_causes_or_parameters());
+        let mut build = build_event();
+        assert_eq!(template.render(&build, false), "nightly");
+        build.details.causes = vec![String::from("Started by user Alice")];
+        build.details.parameters = vec![
+            (String::from("BRANCH"), String::from("main")),
+            (String::from("CLEAN"), String::from("true")),
+        ];
+        assert_eq!(
+            template.render(&build, false),
+            "nightly (Started by user Alice) with BRANCH=main CLEAN=true"
+        );
+    }
+
+    #[test]
+    fn render_builtin() {
+        let mut build = build_event();
+        assert_eq!(
+            Template::builtin(build.transition).render(&build, false),
+            "Build #42 for job 'nightly' on 'jenkins' is broken! Result: FAILURE, \
+             URL: http://jenkins/job/nightly/42/"
+        );
+        build.result = BuildResult::Success;
+        build.transition = Transition::Fixed;
+        build.details.culprits = vec![String::from("johnny")];
+        assert_eq!(
+            Template::builtin(build.transition).render(&build, false),
+            "Build #42 for job 'nightly' on 'jenkins' is fixed! Result: SUCCESS"
+        );
+        build.transition = Transition::StillGreen;
+        assert_eq!(
+            Template::builtin(build.transition).render(&build, false),
+            "Build #42 for job 'nightly' on 'jenkins'! Result: SUCCESS"
+        );
+    }
+
+    #[test]
+    fn render_builtin_with_details() {
+        let mut build = build_event();
+        build.details.culprits = vec![String::from("johnny")];
+        build.details.changes = vec![Change {
+            author: String::from("johnny"),
+            message: String::from("Fix the build"),
+        }];
+        assert_eq!(
+            Template::builtin(build.transition).render(&build, false),
+            "Build #42 for job 'nightly' on 'jenkins' is broken! Result: FAILURE, \
+             URL: http://jenkins/job/nightly/42/, culprits: johnny (johnny: Fix the build)"
+        );
+    }
+
+    #[test]
+    fn render_formatted() {
+        let template = Template::parse("{job}: {result}").unwrap();
+        let mut build = build_event();
+        build.name.0 = String::from("nightly \x0304red\x03");
+        assert_eq!(
+            template.render(&build, true),
+            "\x02nightly \x0304red\x03\x02: \x0304FAILURE\x03"
+        );
+        assert_eq!(template.render(&build, false), "nightly red: FAILURE");
+    }
+
+    #[test]
+    fn reject_invalid_templates() {
+        let err = |template| Template::parse(template).unwrap_err();
+        assert!(err("{nmber}").contains("unknown placeholder {nmber}"));
+        assert!(err("{job").contains("unterminated placeholder"));
+        assert!(err("job}").contains("unmatched '}'"));
+        assert!(err("{? {job}").contains("unterminated optional section"));
+        assert!(err("{? {? {job} ?} ?}").contains("nested optional sections"));
+    }
+}
@@ -1,20 +1,463 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs::File;
 use std::io::Read;
-use toml;
+
+use regex::Regex;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+use crate::carlo::{BuildResult, Template, Transition};
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
     pub sleep: u64,
+    /// Maximum number of builds reported per job and per poll
+    #[serde(default = "default_max_builds")]
+    pub max_builds: usize,
+    /// Maximum number of changes reported for a failed build
+    #[serde(default = "default_max_changes")]
+    pub max_changes: usize,
+    /// Maximum number of failed test cases named for a failed build
+    #[serde(default = "default_max_failed_tests")]
+    pub max_failed_tests: usize,
+    /// Maximum number of console excerpts posted per poll, across all servers
+    #[serde(default = "default_max_console_excerpts")]
+    pub max_console_excerpts: usize,
+    /// Maximum number of jobs listed in a reply to the `status` command
+    #[serde(default = "default_status_lines")]
+    pub status_lines: usize,
+    /// Number of seconds a build triggered from IRC is followed before giving up on it
+    #[serde(default = "default_build_timeout")]
+    pub build_timeout: u64,
+    /// IRC nicks of Jenkins users, by Jenkins user id or commit author email, ignoring case
+    #[serde(default)]
+    pub users: HashMap<String, String>,
+    /// File where the build cache is saved between runs
+    pub state_file: Option<String>,
+    /// Default notification templates
+    #[serde(default)]
+    pub templates: Templates,
     pub job: Vec<JenkinsConfig>,
 }
 
+fn default_max_builds() -> usize {
+    5
+}
+
+fn default_max_changes() -> usize {
+    3
+}
+
+fn default_max_failed_tests() -> usize {
+    3
+}
+
+fn default_max_console_excerpts() -> usize {
+    3
+}
+
+fn default_status_lines() -> usize {
+    5
+}
+
+fn default_build_timeout() -> u64 {
+    4 * 3600
+}
+
+fn default_queue_threshold() -> u64 {
+    600
+}
+
+fn default_depth() -> usize {
+    5
+}
+
+fn default_console_lines() -> usize {
+    10
+}
+
+fn default_console_max_bytes() -> u64 {
+    64 * 1024
+}
+
+fn default_console_interval() -> u64 {
+    300
+}
+
+fn ansi_regex() -> Regex {
+    Regex::new("\x1b\\[[0-9;?]*[A-Za-z]").unwrap()
+}
+
+/// Deserialize a list of build results, rejecting the results that Jenkins does not report.
+fn known_results<'de, D>(deserializer: D) -> Result<Vec<BuildResult>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|result| match BuildResult::from(result) {
+            BuildResult::Unknown(result) => Err(D::Error::custom(format!(
+                "unknown build result \"{}\"",
+                result
+            ))),
+            result => Ok(result),
+        })
+        .collect()
+}
+
 #[derive(Deserialize, Debug)]
 pub struct JenkinsConfig {
+    /// URL of the Jenkins instance or of a folder. If it points to an `api/json` endpoint,
+    /// it is used as is and must select the fields needed by carlo with a `tree` parameter.
     pub server: String,
+    /// Only watch the jobs in this view (`parent/child` for nested views)
+    pub view: Option<String>,
     pub id: String,
     pub user: String,
     pub token: String,
     pub notify: Vec<String>,
+    /// Which builds of this server's jobs should be announced
+    #[serde(default)]
+    pub notify_on: NotifyPolicy,
+    /// Only notify about jobs matching one of these patterns (all jobs if empty)
+    #[serde(default)]
+    pub include: Vec<Pattern>,
+    /// Never notify about jobs matching one of these patterns
+    #[serde(default)]
+    pub exclude: Vec<Pattern>,
+    /// Only notify about builds with one of these results (all results if empty)
+    #[serde(default, deserialize_with = "known_results")]
+    pub results: Vec<BuildResult>,
+    /// Maximum number of nested folders to walk into
+    #[serde(default = "default_depth")]
+    pub depth: usize,
+    /// Announce the start of the builds of jobs matching one of these patterns
+    #[serde(default)]
+    pub notify_on_start: Vec<Pattern>,
+    /// Also send failure notifications privately to the culprits whose nick is known
+    #[serde(default)]
+    pub pm_culprits: bool,
+    /// Rules sending notifications to other destinations than `notify`
+    #[serde(default)]
+    pub route: Vec<Route>,
+    /// Whether only the first matching route is used, or all of them
+    #[serde(default)]
+    pub routing: RoutingMode,
+    /// Notification templates for this server's jobs
+    #[serde(default)]
+    pub templates: Templates,
+    /// Notification templates for specific jobs, by job name
+    #[serde(default)]
+    pub job_templates: HashMap<String, Templates>,
+    /// Post an excerpt of the console output of failed builds
+    pub console: Option<ConsoleConfig>,
+    /// Alert about items waiting too long in the build queue
+    pub queue: Option<QueueConfig>,
+    /// IRC nicks allowed to trigger and abort builds on this server from IRC, in addition to
+    /// the owners
+    #[serde(default)]
+    pub authorized: Vec<String>,
+}
+
+impl JenkinsConfig {
+    /// Whether `server` is a complete API URL rather than the URL of a Jenkins instance.
+    pub fn is_api_url(&self) -> bool {
+        self.server.contains("/api/json")
+    }
+
+    /// The URL of the Jenkins instance itself, without any folder, view or API path.
+    pub fn base_url(&self) -> &str {
+        self.url_before(&["/job/", "/view/", "/api/json"])
+    }
+
+    /// The URL of `job`, given its full name (e.g. `team/project/main`).
+    pub fn job_url(&self, job: &str) -> String {
+        let mut url = self.url_before(&["/view/", "/api/json"]).to_string();
+        job.split('/')
+            .filter(|name| !name.is_empty())
+            .for_each(|name| url.push_str(&format!("/job/{}", encode_path_segment(name))));
+        url
+    }
+
+    /// The part of `server` before the first of `parts`, without trailing slashes.
+    fn url_before(&self, parts: &[&str]) -> &str {
+        let end = parts
+            .iter()
+            .filter_map(|part| self.server.find(part))
+            .min()
+            .unwrap_or(self.server.len());
+        self.server[..end].trim_end_matches('/')
+    }
+
+    /// The URL of the folder or view whose jobs are watched.
+    pub fn view_url(&self) -> String {
+        let mut url = self.server.trim_end_matches('/').to_string();
+        if let Some(ref view) = self.view {
+            view.split('/')
+                .filter(|name| !name.is_empty())
+                .for_each(|name| url.push_str(&format!("/view/{}", name)));
+        }
+        url
+    }
+
+    /// Whether a build of `job` with the given result passes the include/exclude filters.
+    pub fn accepts(&self, job: &str, result: &BuildResult) -> bool {
+        self.accepts_job(job) && (self.results.is_empty() || self.results.contains(result))
+    }
+
+    /// Whether `job` passes the include/exclude filters.
+    pub fn accepts_job(&self, job: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(job)))
+            && !self.exclude.iter().any(|pattern| pattern.matches(job))
+    }
+
+    /// Whether the start of the builds of `job` should be announced.
+    pub fn announces_start(&self, job: &str) -> bool {
+        self.notify_on_start
+            .iter()
+            .any(|pattern| pattern.matches(job))
+    }
+
+    /// Return the destinations for a notification about a build of `job`, according to the
+    /// routing rules. If no rule matches, the destinations in `notify` are used.
+    pub fn destinations(
+        &self,
+        job: &str,
+        result: &BuildResult,
+        transition: Transition,
+    ) -> Vec<String> {
+        self.routed(|route| route.matches(job, result, transition))
+    }
+
+    /// Return the destinations for an announcement about a build of `job` that has just
+    /// started. Only the routing rules without conditions on results and transitions apply.
+    pub fn start_destinations(&self, job: &str) -> Vec<String> {
+        self.routed(|route| {
+            route.results.is_empty() && route.transitions.is_empty() && route.matches_job(job)
+        })
+    }
+
+    /// Return the destinations for an alert about an item of `job` waiting in the build queue:
+    /// the ones of the queue monitor if it has any, the ones of a build start otherwise.
+    pub fn queue_destinations(&self, job: &str) -> Vec<String> {
+        match self.queue {
+            Some(ref queue) if !queue.notify.is_empty() => queue.notify.clone(),
+            _ => self.start_destinations(job),
+        }
+    }
+
+    /// Return the destinations of the routing rules for which `matches` is true, or the ones
+    /// in `notify` if there is none.
+    fn routed<F>(&self, matches: F) -> Vec<String>
+    where
+        F: Fn(&Route) -> bool,
+    {
+        let matching: Vec<&Route> = self.route.iter().filter(|route| matches(route)).collect();
+        let routes = match self.routing {
+            RoutingMode::First => &matching[..matching.len().min(1)],
+            RoutingMode::All => &matching[..],
+        };
+        if routes.is_empty() {
+            return self.notify.clone();
+        }
+        let mut destinations = Vec::new();
+        routes
+            .iter()
+            .flat_map(|route| route.notify.iter())
+            .for_each(|dest| {
+                if !destinations.contains(dest) {
+                    destinations.push(dest.clone());
+                }
+            });
+        destinations
+    }
+}
+
+/// When and where to alert about items waiting in the build queue.
+#[derive(Deserialize, Debug)]
+pub struct QueueConfig {
+    /// Number of seconds an item may wait before an alert is sent
+    #[serde(default = "default_queue_threshold")]
+    pub threshold: u64,
+    /// Where the alerts are sent (according to the routing rules of the server if empty)
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// Percent-encode a job or folder name for use in a URL path.
+fn encode_path_segment(name: &str) -> String {
+    name.bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                char::from(byte).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
+/// Which part of the console output of failed builds is posted, and where.
+#[derive(Deserialize, Debug)]
+pub struct ConsoleConfig {
+    /// Only post the console output of jobs matching one of these patterns (all jobs if
+    /// empty)
+    #[serde(default)]
+    pub jobs: Vec<Pattern>,
+    /// Maximum number of lines in the excerpt
+    #[serde(default = "default_console_lines")]
+    pub lines: usize,
+    /// Only keep the lines matching one of these patterns, e.g. `"/error|FAILED/"` (the last
+    /// lines if empty)
+    #[serde(default)]
+    pub patterns: Vec<Pattern>,
+    /// Maximum number of bytes read from the end of the console output
+    #[serde(default = "default_console_max_bytes")]
+    pub max_bytes: u64,
+    /// Write the excerpt to a file in this directory and only post its URL
+    pub file_dir: Option<String>,
+    /// URL under which the files of `file_dir` are served, required with `file_dir`
+    pub file_url: Option<String>,
+    /// Minimum number of seconds between two excerpts for the same job
+    #[serde(default = "default_console_interval")]
+    pub min_interval: u64,
+    /// Matches the ANSI escape sequences stripped from the console output
+    #[serde(skip, default = "ansi_regex")]
+    pub ansi: Regex,
+}
+
+impl ConsoleConfig {
+    /// Whether the console output of `job` should be posted.
+    pub fn accepts(&self, job: &str) -> bool {
+        self.jobs.is_empty() || self.jobs.iter().any(|pattern| pattern.matches(job))
+    }
+}
+
+/// A routing rule: notifications about builds matching all the conditions are sent to
+/// `notify`, which may be empty to silence them. Empty conditions match everything.
+#[derive(Deserialize, Debug)]
+pub struct Route {
+    #[serde(default)]
+    pub jobs: Vec<Pattern>,
+    #[serde(default, deserialize_with = "known_results")]
+    pub results: Vec<BuildResult>,
+    #[serde(default)]
+    pub transitions: Vec<Transition>,
+    pub notify: Vec<String>,
+}
+
+impl Route {
+    pub fn matches(&self, job: &str, result: &BuildResult, transition: Transition) -> bool {
+        self.matches_job(job)
+            && (self.results.is_empty() || self.results.contains(result))
+            && (self.transitions.is_empty() || self.transitions.contains(&transition))
+    }
+
+    pub fn matches_job(&self, job: &str) -> bool {
+        self.jobs.is_empty() || self.jobs.iter().any(|pattern| pattern.matches(job))
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingMode {
+    /// Use the first matching route
+    #[default]
+    First,
+    /// Use all the matching routes
+    All,
+}
+
+/// A pattern on job names: either a glob such as `nightly-*`, where `*` matches any sequence
+/// of characters and `?` any single character, or a regular expression between slashes, such
+/// as `/^pr-[0-9]+$/`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+pub struct Pattern {
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Pattern, String> {
+        let regex = match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            Some(regex) => regex.to_string(),
+            None => {
+                let glob: Vec<String> = pattern
+                    .split('*')
+                    .map(|part| {
+                        let parts: Vec<String> = part.split('?').map(regex::escape).collect();
+                        parts.join(".")
+                    })
+                    .collect();
+                format!("^{}$", glob.join(".*"))
+            }
+        };
+        Regex::new(&regex)
+            .map(|regex| Pattern { regex })
+            .map_err(|err| format!("invalid pattern \"{}\": {}", pattern, err))
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Pattern, String> {
+        Pattern::parse(&pattern)
+    }
+}
+
+/// Notification templates, by build transition. `default` is used for the transitions that
+/// do not have their own template.
+#[derive(Deserialize, Debug, Default)]
+pub struct Templates {
+    pub default: Option<Template>,
+    pub broken: Option<Template>,
+    pub still_failing: Option<Template>,
+    pub fixed: Option<Template>,
+    pub still_green: Option<Template>,
+    pub interrupted: Option<Template>,
+}
+
+impl Templates {
+    pub fn get(&self, transition: Transition) -> Option<&Template> {
+        let template = match transition {
+            Transition::Broken => &self.broken,
+            Transition::StillFailing => &self.still_failing,
+            Transition::Fixed => &self.fixed,
+            Transition::StillGreen => &self.still_green,
+            Transition::Interrupted => &self.interrupted,
+        };
+        template.as_ref().or(self.default.as_ref())
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyPolicy {
+    /// Announce every completed build
+    #[default]
+    Always,
+    /// Only announce builds that break or fix a job
+    Change,
+    /// Announce every failure, and builds that fix a job
+    FailureAndChange,
+}
+
+impl NotifyPolicy {
+    pub fn accepts(self, transition: Transition) -> bool {
+        match self {
+            NotifyPolicy::Always => true,
+            NotifyPolicy::Change => transition.is_change(),
+            NotifyPolicy::FailureAndChange => {
+                transition.is_change() || transition == Transition::StillFailing
+            }
+        }
+    }
 }
 
 impl Config {
@@ -27,6 +470,268 @@ impl Config {
     }
 
     pub fn from_string(contents: &str) -> Result<Config, String> {
-        toml::from_str(contents).map_err(|err| err.to_string())
+        let mut config: Config = toml::from_str(contents).map_err(|err| err.to_string())?;
+        for (i, j_config) in config.job.iter().enumerate() {
+            if config.job[..i].iter().any(|other| other.id == j_config.id) {
+                return Err(format!("duplicate job id \"{}\"", j_config.id));
+            }
+            if let Some(console) = &j_config.console {
+                if console.file_dir.is_some() && console.file_url.is_none() {
+                    return Err(format!(
+                        "console.file_dir requires console.file_url for job id \"{}\"",
+                        j_config.id
+                    ));
+                }
+            }
+        }
+        config.users = config
+            .users
+            .into_iter()
+            .map(|(user, nick)| (user.to_lowercase(), nick))
+            .collect();
+        Ok(config)
+    }
+
+    /// The IRC nick of a Jenkins user, given their Jenkins user id or email address.
+    pub fn nick(&self, user: &str) -> Option<&str> {
+        self.users.get(&user.to_lowercase()).map(String::as_str)
+    }
+
+    pub fn jenkins(&self, id: &str) -> Option<&JenkinsConfig> {
+        self.job.iter().find(|j_config| j_config.id == id)
+    }
+
+    /// Return the template for a build of `job` on the server `id`. Templates for the job take
+    /// precedence over templates for the server, which take precedence over the global ones.
+    pub fn template(&self, id: &str, job: &str, transition: Transition) -> Option<&Template> {
+        let j_config = self.jenkins(id);
+        j_config
+            .and_then(|j_config| j_config.job_templates.get(job))
+            .and_then(|templates| templates.get(transition))
+            .or_else(|| j_config.and_then(|j_config| j_config.templates.get(transition)))
+            .or_else(|| self.templates.get(transition))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r##"
+sleep = 60
+
+[templates]
+default = "{job}: {result}"
+
+[[job]]
+server = "https://jenkins.example.com/api/json"
+id = "example"
+user = "carlo"
+token = "secret"
+notify = ["#builds"]
+
+[job.templates]
+broken = "{job} is broken!"
+
+[job.job_templates.nightly]
+default = "nightly: {result}"
+"##;
+
+    #[test]
+    fn resolve_templates() {
+        let config = Config::from_string(CONFIG).unwrap();
+        assert_eq!(
+            config.template("example", "nightly", Transition::Broken),
+            Some(&Template::parse("nightly: {result}").unwrap())
+        );
+        assert_eq!(
+            config.template("example", "other", Transition::Broken),
+            Some(&Template::parse("{job} is broken!").unwrap())
+        );
+        assert_eq!(
+            config.template("example", "other", Transition::Fixed),
+            Some(&Template::parse("{job}: {result}").unwrap())
+        );
+        assert_eq!(
+            config.template("unknown", "nightly", Transition::Broken),
+            Some(&Template::parse("{job}: {result}").unwrap())
+        );
+    }
+
+    #[test]
+    fn match_patterns() {
+        let glob = Pattern::parse("nightly-*.?").unwrap();
+        assert!(glob.matches("nightly-linux.1"));
+        assert!(glob.matches("nightly-.x"));
+        assert!(!glob.matches("nightly-linux"));
+        assert!(!glob.matches("nightly-linux_1"));
+        assert!(!glob.matches("pr-nightly-linux.1"));
+
+        let regex = Pattern::parse("/^pr-[0-9]+$/").unwrap();
+        assert!(regex.matches("pr-123"));
+        assert!(!regex.matches("pr-abc"));
+
+        assert!(Pattern::parse("/pr-[/").is_err());
+    }
+
+    #[test]
+    fn filter_jobs() {
+        let config = CONFIG.replace(
+            "[job.templates]",
+            "include = [\"nightly-*\", \"release\"]\n\
+             exclude = [\"/-broken$/\"]\n\
+             results = [\"FAILURE\", \"unstable\"]\n\n\
+             [job.templates]",
+        );
+        let config = Config::from_string(&config).unwrap();
+        let j_config = config.jenkins("example").unwrap();
+        assert!(j_config.accepts("nightly-linux", &BuildResult::Failure));
+        assert!(j_config.accepts("release", &BuildResult::Unstable));
+        assert!(!j_config.accepts("release", &BuildResult::Success));
+        assert!(!j_config.accepts("nightly-broken", &BuildResult::Failure));
+        assert!(!j_config.accepts("pr-12", &BuildResult::Failure));
+    }
+
+    #[test]
+    fn route_notifications() {
+        let routes = r##"
+[[job.route]]
+jobs = ["release-*"]
+results = ["FAILURE"]
+notify = ["#release", "relmgr"]
+
+[[job.route]]
+transitions = ["broken", "fixed"]
+notify = ["#release", "#builds"]
+
+[[job.route]]
+jobs = ["pr-*"]
+notify = []
+
+[job.templates]"##;
+        let config = CONFIG.replace("[job.templates]", routes);
+        let mut config = Config::from_string(&config).unwrap();
+        let destinations = |config: &Config, job, result, transition| {
+            config
+                .jenkins("example")
+                .unwrap()
+                .destinations(job, &result, transition)
+        };
+        let failure = BuildResult::Failure;
+        assert_eq!(
+            destinations(&config, "release-1", failure.clone(), Transition::Broken),
+            vec!["#release", "relmgr"]
+        );
+        assert_eq!(
+            destinations(&config, "nightly", failure.clone(), Transition::Broken),
+            vec!["#release", "#builds"]
+        );
+        assert_eq!(
+            destinations(
+                &config,
+                "nightly",
+                failure.clone(),
+                Transition::StillFailing
+            ),
+            vec!["#builds"]
+        );
+        assert!(
+            destinations(&config, "pr-1", failure.clone(), Transition::StillFailing).is_empty()
+        );
+
+        let j_config = config.jenkins("example").unwrap();
+        assert_eq!(j_config.start_destinations("release-1"), vec!["#builds"]);
+        assert!(j_config.start_destinations("pr-1").is_empty());
+
+        config.job[0].queue = Some(QueueConfig {
+            threshold: 600,
+            notify: Vec::new(),
+        });
+        let j_config = config.jenkins("example").unwrap();
+        assert_eq!(j_config.queue_destinations("release-1"), vec!["#builds"]);
+        assert!(j_config.queue_destinations("pr-1").is_empty());
+        config.job[0].queue.as_mut().unwrap().notify = vec![String::from("#ops")];
+        let j_config = config.jenkins("example").unwrap();
+        assert_eq!(j_config.queue_destinations("pr-1"), vec!["#ops"]);
+
+        config.job[0].routing = RoutingMode::All;
+        assert_eq!(
+            destinations(&config, "release-1", failure, Transition::Broken),
+            vec!["#release", "relmgr", "#builds"]
+        );
+    }
+
+    #[test]
+    fn build_urls() {
+        let mut config = Config::from_string(CONFIG).unwrap();
+        let j_config = &mut config.job[0];
+        assert!(j_config.is_api_url());
+
+        j_config.server = String::from("https://example.com/jenkins/job/team/");
+        j_config.view = Some(String::from("backend/nightly"));
+        assert!(!j_config.is_api_url());
+        assert_eq!(
+            j_config.view_url(),
+            "https://example.com/jenkins/job/team/view/backend/view/nightly"
+        );
+        assert_eq!(j_config.base_url(), "https://example.com/jenkins");
+        assert_eq!(
+            j_config.job_url("project/main branch"),
+            "https://example.com/jenkins/job/team/job/project/job/main%20branch"
+        );
+
+        j_config.server = String::from("https://example.com/view/all/api/json?tree=jobs[name]");
+        assert_eq!(j_config.base_url(), "https://example.com");
+        assert_eq!(
+            j_config.job_url("nightly"),
+            "https://example.com/job/nightly"
+        );
+    }
+
+    #[test]
+    fn reject_invalid_templates() {
+        let config = CONFIG.replace("{job} is broken!", "{jbo} is broken!");
+        let err = Config::from_string(&config).unwrap_err();
+        assert!(err.contains("unknown placeholder {jbo}"), "{}", err);
+    }
+
+    #[test]
+    fn reject_unknown_results() {
+        let config = CONFIG.replace(
+            "[job.templates]",
+            "results = [\"FAILUR\"]\n\n[job.templates]",
+        );
+        let err = Config::from_string(&config).unwrap_err();
+        assert!(err.contains("unknown build result \"FAILUR\""), "{}", err);
+
+        let config = CONFIG.replace(
+            "[job.templates]",
+            "[[job.route]]\nresults = [\"SUCESS\"]\nnotify = []\n\n[job.templates]",
+        );
+        let err = Config::from_string(&config).unwrap_err();
+        assert!(err.contains("unknown build result \"SUCESS\""), "{}", err);
+    }
+
+    #[test]
+    fn require_console_file_url() {
+        let config = CONFIG.replace(
+            "[job.templates]",
+            "[job.console]\nfile_dir = \"/var/www/console\"\n\n[job.templates]",
+        );
+        let err = Config::from_string(&config).unwrap_err();
+        assert!(err.contains("requires console.file_url"), "{}", err);
+        let config = config.replace(
+            "file_dir = \"/var/www/console\"",
+            "file_dir = \"/var/www/console\"\nfile_url = \"https://example.com/console\"",
+        );
+        assert!(Config::from_string(&config).is_ok());
+    }
+
+    #[test]
+    fn reject_duplicate_ids() {
+        let job = &CONFIG[CONFIG.find("[[job]]").unwrap()..];
+        let config = format!("{}\n{}", CONFIG, job);
+        let err = Config::from_string(&config).unwrap_err();
+        assert!(err.contains("duplicate job id \"example\""), "{}", err);
     }
 }
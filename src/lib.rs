@@ -1,9 +1,11 @@
 extern crate irc;
 #[macro_use]
 extern crate log;
+extern crate regex;
 extern crate reqwest;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate toml;
 
 #[cfg(test)]